@@ -0,0 +1,102 @@
+// arbitrary output variables, per-pixel surface data rendered next to the beauty image
+// for denoisers, compositing and debugging
+
+use crate::math::Vec3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+  Depth,    // distance along the primary ray to the first hit, infinite on misses
+  Normal,   // outward facing world normal of the first hit, so flipped geometry shows up
+  Albedo,   // material albedo of the first hit
+  ObjectId, // index of the first object hit in the scene, -1 on misses
+  Samples,  // number of samples taken for the pixel
+}
+
+impl Aov {
+  pub const ALL: [Aov; 5] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::Samples];
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "depth"   => Some(Aov::Depth),
+      "normal"  => Some(Aov::Normal),
+      "albedo"  => Some(Aov::Albedo),
+      "id"      => Some(Aov::ObjectId),
+      "samples" => Some(Aov::Samples),
+      _ => None,
+    }
+  }
+
+  // layer name in multi-layer EXR files
+  pub fn layer(&self) -> &'static str {
+    match self {
+      Aov::Depth    => "depth",
+      Aov::Normal   => "normal",
+      Aov::Albedo   => "albedo",
+      Aov::ObjectId => "id",
+      Aov::Samples  => "samples",
+    }
+  }
+
+  // names of the channels stored for the pass, single channel passes use the first component
+  pub fn channels(&self) -> &'static [&'static str] {
+    match self {
+      Aov::Depth    => &["Z"],
+      Aov::Normal   => &["X", "Y", "Z"],
+      Aov::Albedo   => &["R", "G", "B"],
+      Aov::ObjectId => &["id"],
+      Aov::Samples  => &["count"],
+    }
+  }
+}
+
+// what the camera rays of one pixel found, depth and object come from the first sample
+// that hits something since blending them across edges gives values that belong to no surface,
+// normal and albedo are averaged over every sample
+#[derive(Clone, Copy)]
+pub struct PixelAovs {
+  pub depth  : f64,
+  pub normal : Vec3,
+  pub albedo : Vec3,
+  pub object : Option<usize>,
+  pub samples: usize,
+}
+
+impl Default for PixelAovs {
+  fn default() -> Self {
+    PixelAovs {
+      depth  : f64::INFINITY,
+      normal : Vec3::zero(),
+      albedo : Vec3::zero(),
+      object : None,
+      samples: 0,
+    }
+  }
+}
+
+impl PixelAovs {
+  pub fn value(&self, aov: Aov) -> [f32; 3] {
+    let vector = |v: Vec3| [v.x as f32, v.y as f32, v.z as f32];
+    match aov {
+      Aov::Depth    => [self.depth as f32, 0.0, 0.0],
+      Aov::Normal   => vector(self.normal),
+      Aov::Albedo   => vector(self.albedo),
+      Aov::ObjectId => [self.object.map_or(-1.0, |index| index as f32), 0.0, 0.0],
+      Aov::Samples  => [self.samples as f32, 0.0, 0.0],
+    }
+  }
+}
+
+// one pass for the whole image in row-major order
+pub struct AovBuffer {
+  pub aov   : Aov,
+  pub pixels: Vec<[f32; 3]>,
+}
+
+impl AovBuffer {
+  pub fn new(aov: Aov, width: usize, height: usize) -> Self {
+    AovBuffer {
+      aov,
+      pixels: vec![[0.0; 3]; width * height],
+    }
+  }
+}
@@ -1,15 +1,43 @@
 use crate::Point3;
 use crate::Vec3;
+use crate::math::EPSILON;
+use crate::ray::Ray;
+use crate::sampler::SamplerKind;
+use std::f64::consts::PI;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Viewport {
-  pub u       : Vec3,
-  pub v       : Vec3,
-  pub origin  : Point3, // top-left corner
-  pub pdu                 : Vec3,     // pixel spacing in u direction
-  pub pdv                 : Vec3,     // pixel spacing in v direction
-  pub p00                 : Point3,   // location of top left pixel
-  
+  pub pdu: Vec3,   // pixel spacing in u direction
+  pub pdv: Vec3,   // pixel spacing in v direction
+  pub p00: Point3, // location of top left pixel
+}
+
+// orthonormal camera frame, `w` points away from the view direction
+#[derive(Clone)]
+pub struct Basis {
+  pub u: Vec3, // right
+  pub v: Vec3, // up
+  pub w: Vec3, // backward
+}
+
+pub const DEFAULT_SAMPLING_RATE: usize = 4;
+
+// keeps sampling a pixel in batches of `sampling_rate` samples while the standard error
+// of its mean luminance, relative to that mean, stays above `threshold`
+#[derive(Clone, Copy)]
+pub struct AdaptiveSampling {
+  pub threshold  : f64,
+  pub max_samples: usize,
+}
+
+// darker pixels are measured against this luminance, otherwise black would never converge
+const MIN_LUMINANCE: f64 = 0.05;
+
+impl AdaptiveSampling {
+  pub fn converged(&self, mean: f64, variance: f64, samples: usize) -> bool {
+    let error = (variance / samples as f64).sqrt();
+    error / mean.max(MIN_LUMINANCE) <= self.threshold
+  }
 }
 
 #[derive(Clone)]
@@ -17,15 +45,45 @@ pub struct Camera {
   pub aspect_ratio    : f64,
   pub image_width     : usize,
   pub position        : Point3,
-  pub focal_length    : f64,
+  pub direction       : Vec3, // unit view direction
+  pub vup             : Vec3, // world up used to level the camera
+  pub fov_degrees     : f64,  // vertical field of view
+  pub basis           : Basis,
+  pub focus_distance  : f64,  // distance to the plane in perfect focus, where the viewport sits
+  pub aperture        : f64,  // lens diameter, 0 for a pinhole
+  pub aperture_blades : u32,  // polygonal aperture with this many blades, 0 or fewer than 3 for a disk
+  pub blade_rotation  : f64,  // rotation of the aperture polygon in degrees
   pub viewport        : Viewport,
   pub image_height    : usize,
   pub sampling_rate   : usize,
-  pub max_depth       : u32, // maximum ray bounces
+  pub tile_size       : usize, // edge length of the square tiles handed to render threads
+  pub threads         : usize, // 0 uses every available core
+  pub seed            : u64,   // renders with the same seed and settings are identical
+  pub sampler         : SamplerKind,
+  pub adaptive        : Option<AdaptiveSampling>, // None takes `sampling_rate` samples everywhere
+}
+
+impl Basis {
+  // builds the frame looking along `direction`, falls back to another up vector
+  // when `vup` is parallel to the view direction
+  pub fn new(direction: Vec3, vup: Vec3) -> Self {
+    let w = -direction.unit();
+    let mut u = vup.cross(&w);
+    if u.length_squared() < EPSILON {
+      let fallback = if w.y.abs() < 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) };
+      u = fallback.cross(&w);
+    }
+    let u = u.unit();
+    let v = w.cross(&u);
+
+    Basis { u, v, w }
+  }
 }
 
 impl Camera {
+  // camera at `position` looking along `direction`, as described in `.rt` files
   pub fn new(position: Point3,
+    direction: Vec3,
     fov_degrees: f64,
     aspect_ratio: f64,
     image_width: usize) -> Self {
@@ -33,45 +91,140 @@ impl Camera {
     let mut image_height = (image_width as f64 / aspect_ratio) as usize;
     image_height = image_height.max(1); 
     let actual_ratio = image_width as f64 / image_height as f64;
-    
-    let focal_length = 1.0;
-    let viewport_height = 2.0 * focal_length * (fov_degrees.to_radians() / 2.0).tan();
-    let viewport_width = viewport_height * aspect_ratio;
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let basis = Basis::new(direction, vup);
+
+    let mut camera = Camera {
+      aspect_ratio  : actual_ratio,
+      image_width,
+      position,
+      direction     : -basis.w,
+      vup,
+      fov_degrees,
+      basis,
+      focus_distance: 1.0,
+      aperture      : 0.0,
+      aperture_blades: 0,
+      blade_rotation: 0.0,
+      viewport      : Viewport::default(),
+      image_height,
+      sampling_rate : DEFAULT_SAMPLING_RATE,
+      tile_size: 32,
+      threads: 0,
+      seed: 0,
+      sampler: SamplerKind::default(),
+      adaptive: None,
+    };
+    camera.update_viewport();
+    camera
+  }
+
+  // camera at `look_from` aimed at `look_at`, leveled with `vup`
+  pub fn look_at(look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    fov_degrees: f64,
+    aspect_ratio: f64,
+    image_width: usize) -> Self {
+
+    let mut camera = Camera::new(look_from, look_at - look_from, fov_degrees, aspect_ratio, image_width);
+    camera.orient(look_at - look_from, vup);
+    camera
+  }
+
+  // points the camera along a new direction and recomputes the viewport
+  pub fn orient(&mut self, direction: Vec3, vup: Vec3) {
+    self.basis     = Basis::new(direction, vup);
+    self.direction = -self.basis.w;
+    self.vup       = vup;
+    self.update_viewport();
+  }
+
+  // derives the viewport from the position, basis and field of view
+  pub fn update_viewport(&mut self) {
+    let viewport_height = 2.0 * self.focus_distance * (self.fov_degrees.to_radians() / 2.0).tan();
+    let viewport_width = viewport_height * self.aspect_ratio;
     
     // viewport edge vectors
     // Y-axis is inverted relatively to the traversal of the viewport (top-to-bottom)
-    let viewport_u = Vec3::new(viewport_width, 0.0, 0.0);
-    let viewport_v = Vec3::new(0.0, -viewport_height, 0.0);
+    let viewport_u = viewport_width * self.basis.u;
+    let viewport_v = viewport_height * -self.basis.v;
     
-    let viewport_top_left = position
-                          - Vec3::new(0.0, 0.0, focal_length)
+    let viewport_top_left = self.position
+                          - self.focus_distance * self.basis.w
                           - viewport_u / 2.0
                           - viewport_v / 2.0;
 
-    let pdu         = viewport_u / (image_width as f64);
-    let pdv         = viewport_v / (image_height as f64);
+    let pdu         = viewport_u / (self.image_width as f64);
+    let pdv         = viewport_v / (self.image_height as f64);
     let p00: Point3 = viewport_top_left + pdu / 2.0 + pdv / 2.0;
 
-    let viewport = Viewport {
-      u: viewport_u,
-      v: viewport_v,
-      origin: viewport_top_left,
-      pdu,
-      pdv,
-      p00,
+    self.viewport = Viewport { pdu, pdv, p00 };
+  }
+
+  // primary ray through pixel (i, j), `offset` jitters the position inside the pixel
+  // and `lens` is a uniform sample in [0, 1)^2 used to pick a point on the aperture
+  pub fn ray(&self, i: usize, j: usize, offset: (f64, f64), lens: (f64, f64)) -> Ray {
+    let pixel = self.viewport.p00
+              + (i as f64 + offset.0) * self.viewport.pdu
+              + (j as f64 + offset.1) * self.viewport.pdv;
+
+    let origin = if self.aperture > 0.0 {
+      let (x, y) = self.sample_aperture(lens);
+      let radius = self.aperture / 2.0;
+      self.position + radius * (x * self.basis.u + y * self.basis.v)
+    } else {
+      self.position
     };
-      
 
-    Camera {
-      aspect_ratio  : actual_ratio,
-      image_width,
-      position,
-      focal_length  : 1.0,
-      viewport,
-      image_height,
-      sampling_rate : 4,
-      max_depth: 10, 
+    Ray::new(origin, (pixel - origin).unit())
+  }
+
+  // maps a uniform sample onto the unit aperture shape
+  fn sample_aperture(&self, (s, t): (f64, f64)) -> (f64, f64) {
+    if self.aperture_blades < 3 {
+      // concentric mapping keeps stratified samples well spread on the disk
+      return concentric_disk(s, t);
     }
+
+    // pick a blade triangle, then a uniform point inside it
+    let blades = self.aperture_blades as f64;
+    let scaled = s * blades;
+    let blade = scaled.floor().min(blades - 1.0);
+    let s = scaled - blade;
+
+    let step = 2.0 * PI / blades;
+    let start = self.blade_rotation.to_radians() + blade * step;
+    let (a, b) = ((start.cos(), start.sin()), ((start + step).cos(), (start + step).sin()));
+
+    let (mut u, mut v) = (s, t);
+    if u + v > 1.0 {
+      u = 1.0 - u;
+      v = 1.0 - v;
+    }
+    (u * a.0 + v * b.0, u * a.1 + v * b.1)
   }
 
-  } 
+  pub fn thread_count(&self) -> usize {
+    if self.threads > 0 {
+      return self.threads;
+    }
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+  }
+
+  }
+
+// shirley-chiu concentric mapping from the unit square to the unit disk
+fn concentric_disk(s: f64, t: f64) -> (f64, f64) {
+  let (a, b) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+  if a == 0.0 && b == 0.0 {
+    return (0.0, 0.0);
+  }
+
+  let (r, theta) = if a.abs() > b.abs() {
+    (a, PI / 4.0 * (b / a))
+  } else {
+    (b, PI / 2.0 - PI / 4.0 * (a / b))
+  };
+  (r * theta.cos(), r * theta.sin())
+}
@@ -0,0 +1,238 @@
+// command line parsing
+
+use std::path::PathBuf;
+
+use crate::aov::Aov;
+use crate::camera::{ AdaptiveSampling, DEFAULT_SAMPLING_RATE };
+use crate::sampler::SamplerKind;
+use crate::output::{ ExrPrecision, ImageFormat };
+use crate::tonemap::{ DisplayTransform, ToneMapper };
+
+pub const USAGE: &str = "\
+usage:
+  raytreizer [scene]                   open an interactive window
+  raytreizer render [scene] [options]  render once and write an image
+
+scenes are .rt files or glTF 2.0 .gltf and .glb files, the camera of a glTF scene
+is its first perspective one
+
+render options:
+  -o, --output <file>   output image, format taken from the extension (png, ppm, exr, hdr),
+                        png and ppm are tone mapped, exr and hdr store linear radiance
+      --half            store exr channels as 16-bit half floats instead of 32-bit floats
+      --aov <list>      extra exr layers, comma separated from depth, normal, albedo, id
+                        and samples, or all
+      --spp <n>         samples per pixel
+      --width <px>      image width, defaults to 1280
+      --height <px>     image height, defaults to width * 9 / 16
+      --depth <n>       bounces before russian roulette may end a path, defaults to 3
+      --threads <n>     render threads, 0 uses every core
+      --seed <n>        random seed, the same seed gives the same image on any thread count
+      --sampler <name>  independent, stratified, halton or sobol, defaults to sobol
+      --adaptive <err>  keep sampling pixels in batches of --spp while their relative
+                        error is above <err>, 0.02 is a good start
+      --max-spp <n>     sample limit per pixel with --adaptive, defaults to 16 times --spp
+      --heatmap <file>  also write a png or ppm showing the samples taken per pixel
+      --tonemap <op>    clamp, reinhard or aces, defaults to clamp
+      --exposure <ev>   exposure adjustment in stops
+      --stats           print the bvh shape and the rays traced to stderr
+
+exit codes:
+  0 success, 2 invalid arguments, 3 scene could not be loaded, 4 image could not be written";
+
+pub const DEFAULT_WIDTH: usize = 1280;
+pub const DEFAULT_ASPECT_RATIO: f64 = 16.0 / 9.0;
+
+pub enum Command {
+  Window { scene: Option<PathBuf> },
+  Render(RenderOptions),
+  Help,
+}
+
+pub struct RenderOptions {
+  pub scene  : Option<PathBuf>,
+  pub output : PathBuf,
+  pub format : ImageFormat,
+  pub spp    : Option<usize>,
+  pub width  : usize,
+  pub height : Option<usize>,
+  pub depth  : Option<u32>,
+  pub threads: Option<usize>,
+  pub seed   : u64,
+  pub sampler: Option<SamplerKind>,
+  pub adaptive: Option<AdaptiveSampling>,
+  pub heatmap: Option<(PathBuf, ImageFormat)>,
+  pub display: DisplayTransform,
+  pub aovs   : Vec<Aov>,
+  pub stats  : bool,
+}
+
+impl RenderOptions {
+  pub fn aspect_ratio(&self) -> f64 {
+    match self.height {
+      Some(height) => self.width as f64 / height as f64,
+      None => DEFAULT_ASPECT_RATIO,
+    }
+  }
+}
+
+// `args` excludes the program name
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+  let mut args = args.into_iter();
+
+  let Some(first) = args.next() else {
+    return Ok(Command::Window { scene: None });
+  };
+
+  match first.as_str() {
+    "-h" | "--help" => Ok(Command::Help),
+    "render" => parse_render(args),
+    flag if flag.starts_with('-') => Err(format!("unknown option `{flag}`")),
+    _ => match args.next() {
+      Some(extra) => Err(format!("unexpected argument `{extra}`")),
+      None => Ok(Command::Window { scene: Some(PathBuf::from(first)) }),
+    },
+  }
+}
+
+fn parse_render<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+  let mut scene  : Option<PathBuf> = None;
+  let mut output : Option<PathBuf> = None;
+  let mut spp    : Option<usize>   = None;
+  let mut width  : Option<usize>   = None;
+  let mut height : Option<usize>   = None;
+  let mut depth  : Option<u32>     = None;
+  let mut threads: Option<usize>   = None;
+  let mut seed   : u64             = 0;
+  let mut sampler: Option<SamplerKind> = None;
+  let mut threshold: Option<f64>   = None;
+  let mut max_spp  : Option<usize> = None;
+  let mut heatmap  : Option<PathBuf> = None;
+  let mut display = DisplayTransform::default();
+  let mut half    = false;
+  let mut aovs   : Vec<Aov>        = Vec::new();
+  let mut stats   = false;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "-o" | "--output" => output  = Some(PathBuf::from(value(&arg, args.next())?)),
+      "--spp"           => spp     = Some(positive(&arg, args.next())?),
+      "--width"         => width   = Some(positive(&arg, args.next())?),
+      "--height"        => height  = Some(positive(&arg, args.next())?),
+      "--depth"         => depth   = Some(number(&arg, args.next())?),
+      "--threads"       => threads = Some(number(&arg, args.next())?),
+      "--seed"          => seed    = number(&arg, args.next())?,
+      "--sampler"       => {
+        let name = value(&arg, args.next())?;
+        sampler = Some(SamplerKind::from_name(&name).ok_or_else(|| {
+          format!("unknown sampler `{name}`, use independent, stratified, halton or sobol")
+        })?);
+      }
+      "--exposure"      => display.exposure = finite(&arg, args.next())?,
+      "--half"          => half = true,
+      "--stats"         => stats = true,
+      "--adaptive"      => threshold = Some(number(&arg, args.next())?),
+      "--max-spp"       => max_spp   = Some(positive(&arg, args.next())?),
+      "--heatmap"       => heatmap   = Some(PathBuf::from(value(&arg, args.next())?)),
+      "--aov"           => {
+        for name in value(&arg, args.next())?.split(',').map(str::trim) {
+          let requested = match name {
+            "all" => Aov::ALL.to_vec(),
+            _ => vec![Aov::from_name(name).ok_or_else(|| format!(
+              "unknown aov `{name}`, use depth, normal, albedo, id, samples or all",
+            ))?],
+          };
+          for aov in requested {
+            if !aovs.contains(&aov) {
+              aovs.push(aov);
+            }
+          }
+        }
+      }
+      "--tonemap"       => {
+        let name = value(&arg, args.next())?;
+        display.tone_mapper = ToneMapper::from_name(&name)
+          .ok_or_else(|| format!("unknown tone mapper `{name}`, use clamp, reinhard or aces"))?;
+      }
+      flag if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+      _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+      _ => return Err(format!("unexpected argument `{arg}`")),
+    }
+  }
+
+  let output = output.ok_or("missing output file, pass it with -o")?;
+  let format = match ImageFormat::from_path(&output) {
+    Some(ImageFormat::Exr(_)) if half => ImageFormat::Exr(ExrPrecision::Half),
+    Some(_) if half => return Err("`--half` only applies to .exr output".to_string()),
+    Some(format) => format,
+    None => return Err(format!(
+      "cannot tell the image format of `{}`, use .png, .ppm, .exr or .hdr",
+      output.display(),
+    )),
+  };
+  if !aovs.is_empty() && !matches!(format, ImageFormat::Exr(_)) {
+    return Err("`--aov` needs .exr output to store the extra layers".to_string());
+  }
+
+  let adaptive = match (threshold, max_spp) {
+    (Some(threshold), _) if !threshold.is_finite() || threshold <= 0.0 => return Err("`--adaptive` must be greater than zero".to_string()),
+    (Some(threshold), max_spp) => Some(AdaptiveSampling {
+      threshold,
+      max_samples: max_spp.unwrap_or(16 * spp.unwrap_or(DEFAULT_SAMPLING_RATE)),
+    }),
+    (None, Some(_)) => return Err("`--max-spp` only applies with `--adaptive`".to_string()),
+    (None, None) => None,
+  };
+
+  let heatmap = match heatmap {
+    Some(path) => match ImageFormat::from_path(&path) {
+      Some(format @ (ImageFormat::Png | ImageFormat::Ppm)) => Some((path, format)),
+      _ => return Err(format!("heatmap `{}` must be a .png or .ppm file", path.display())),
+    },
+    None => None,
+  };
+
+  Ok(Command::Render(RenderOptions {
+    scene,
+    output,
+    format,
+    spp,
+    width: width.unwrap_or(DEFAULT_WIDTH),
+    height,
+    depth,
+    threads,
+    seed,
+    sampler,
+    adaptive,
+    heatmap,
+    display,
+    aovs,
+    stats,
+  }))
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+  value.ok_or_else(|| format!("`{flag}` expects a value"))
+}
+
+fn number<T: std::str::FromStr>(flag: &str, arg: Option<String>) -> Result<T, String> {
+  let arg = value(flag, arg)?;
+  arg.parse().map_err(|_| format!("`{flag}` expects a number, found `{arg}`"))
+}
+
+// rejects inf and NaN, which `f64` parses
+fn finite(flag: &str, arg: Option<String>) -> Result<f64, String> {
+  let n: f64 = number(flag, arg)?;
+  if !n.is_finite() {
+    return Err(format!("`{flag}` must be a finite number"));
+  }
+  Ok(n)
+}
+
+fn positive(flag: &str, arg: Option<String>) -> Result<usize, String> {
+  match number(flag, arg)? {
+    0 => Err(format!("`{flag}` must be greater than zero")),
+    n => Ok(n),
+  }
+}
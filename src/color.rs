@@ -19,24 +19,16 @@ impl Color {
   }
 
   // top byte is ignored because alpha blending isnt supported by minifb
-  pub fn to_rgb_bytes(&self) -> u32 {
-    match *self {
+  pub fn to_rgb_bytes(self) -> u32 {
+    match self {
       Color::Rgb(v) => {
-        let (r, g, b) = (
-          (v.x * 255.0) as u32,
-          (v.y * 255.0) as u32,
-          (v.z * 255.0) as u32
-        );
+        let (r, g, b) = (to_byte(v.x), to_byte(v.y), to_byte(v.z));
         
         (r << 16) | (g << 8) | b
       }
       Color::Argb(a,v) => {
-        let (r, g, b) = (
-          (v.x * 255.0) as u32,
-          (v.y * 255.0) as u32,
-          (v.z * 255.0) as u32
-        );
-        let alpha = (a * 255.0) as u32;
+        let (r, g, b) = (to_byte(v.x), to_byte(v.y), to_byte(v.z));
+        let alpha = to_byte(a);
         (alpha << 24) | (r << 16) | (g << 8) | b
       }
     }
@@ -48,23 +40,27 @@ impl Color {
       Color::Argb(a, _) => a,
     }
   }
-  
-  pub fn gamma_correct(&self, gamma: f64) -> Color {
-    match *self {
-      Color::Rgb(v) => 
-        Color::rgb(
-          v.x.powf(1.0 / gamma),
-          v.y.powf(1.0 / gamma),
-          v.z.powf(1.0 / gamma),
-        ),
+}
 
-      Color::Argb(a, v) => 
-        Color::argb(
-          a,
-          v.x.powf(1.0 / gamma),
-          v.y.powf(1.0 / gamma),
-          v.z.powf(1.0 / gamma),
-        ),
-    }
+// quantizes a [0, 1] channel, out of range values saturate instead of wrapping
+fn to_byte(c: f64) -> u32 {
+  (c.clamp(0.0, 1.0) * 255.0).round() as u32
+}
+
+// sRGB transfer function, encodes a linear [0, 1] channel for display
+pub fn linear_to_srgb(c: f64) -> f64 {
+  if c <= 0.0031308 {
+    12.92 * c
+  } else {
+    1.055 * c.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+// inverse of `linear_to_srgb`, decodes 8-bit image data back to linear light
+pub fn srgb_to_linear(c: f64) -> f64 {
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
   }
 }
@@ -1,5 +1,44 @@
 use std::ops::{ Index, IndexMut };
 
+use crate::math::Vec3;
+
+// rectangular block of pixels rendered as one unit of work
+#[derive(Clone, Copy)]
+pub struct Tile {
+  pub x     : usize,
+  pub y     : usize,
+  pub width : usize,
+  pub height: usize,
+}
+
+impl Tile {
+  // splits a width x height image in row-major order into tiles of at most `size` x `size` pixels
+  pub fn split(width: usize, height: usize, size: usize) -> Vec<Tile> {
+    let size = size.max(1);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size) {
+      for x in (0..width).step_by(size) {
+        tiles.push(Tile {
+          x,
+          y,
+          width : size.min(width - x),
+          height: size.min(height - y),
+        });
+      }
+    }
+    tiles
+  }
+
+  // copies the row-major pixels of this tile into an image `stride` pixels wide
+  pub fn write<T: Copy>(&self, image: &mut [T], stride: usize, pixels: &[T]) {
+    for row in 0..self.height {
+      let start = (self.y + row) * stride + self.x;
+      image[start..start + self.width]
+        .copy_from_slice(&pixels[row * self.width..(row + 1) * self.width]);
+    }
+  }
+}
+
 pub struct FrameBuffer {
   pub width: usize,
   pub height: usize,
@@ -14,6 +53,72 @@ impl FrameBuffer {
       buf: vec![0; width * height],
     }
   }
+
+}
+
+// linear RGB radiance per pixel in row-major order, kept apart from display conversion
+// so it can be tone mapped or written out as HDR
+pub struct RadianceBuffer {
+  pub width : usize,
+  pub height: usize,
+  pub pixels: Vec<[f32; 3]>,
+}
+
+impl RadianceBuffer {
+  pub fn new(width: usize, height: usize) -> Self {
+    RadianceBuffer {
+      width,
+      height,
+      pixels: vec![[0.0; 3]; width * height],
+    }
+  }
+
+  pub fn from_vec3(width: usize, height: usize, values: &[Vec3]) -> Self {
+    RadianceBuffer {
+      width,
+      height,
+      pixels: values.iter().map(|v| [v.x as f32, v.y as f32, v.z as f32]).collect(),
+    }
+  }
+}
+
+// running sum of linear radiance over successive frames, for progressive refinement
+pub struct Accumulator {
+  pub width  : usize,
+  pub height : usize,
+  pub samples: usize, // samples per pixel accumulated so far
+  sum        : Vec<Vec3>,
+}
+
+impl Accumulator {
+  pub fn new(width: usize, height: usize) -> Self {
+    Accumulator {
+      width,
+      height,
+      samples: 0,
+      sum: vec![Vec3::zero(); width * height],
+    }
+  }
+
+  // drops everything accumulated, for when the camera or scene changed
+  pub fn reset(&mut self) {
+    self.samples = 0;
+    self.sum.fill(Vec3::zero());
+  }
+
+  // adds a frame holding the per-pixel average of `samples` samples
+  pub fn add(&mut self, frame: &RadianceBuffer, samples: usize) {
+    for (sum, value) in self.sum.iter_mut().zip(&frame.pixels) {
+      *sum += Vec3::new(value[0] as f64, value[1] as f64, value[2] as f64) * samples as f64;
+    }
+    self.samples += samples;
+  }
+
+  pub fn average(&self) -> RadianceBuffer {
+    let scale = 1.0 / self.samples.max(1) as f64;
+    let average: Vec<Vec3> = self.sum.iter().map(|v| *v * scale).collect();
+    RadianceBuffer::from_vec3(self.width, self.height, &average)
+  }
 }
 
 impl Index<(usize, usize)> for FrameBuffer {
@@ -0,0 +1,575 @@
+// readers for the linear radiance formats, the counterpart of the hdr and exr writers
+// in output.rs
+//
+// Radiance pictures are read flat or with the run-length encoded scanlines every
+// modern writer produces, the old run-length scheme and XYZE pictures are not supported
+//
+// OpenEXR files must be single part scanline images, stored uncompressed or with the
+// RLE, ZIPS or ZIP compression, other compressions (PIZ, PXR24, B44, DWA) are refused
+
+use std::fs;
+use std::path::Path;
+
+use crate::framebuffer::RadianceBuffer;
+
+// picks the reader from the file extension
+pub fn read_radiance(path: &Path) -> Result<RadianceBuffer, String> {
+  let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+  let bytes = fs::read(path).map_err(|e| e.to_string())?;
+  match extension.as_str() {
+    "hdr" => read_hdr(&bytes),
+    "exr" => read_exr(&bytes),
+    _ => Err("unsupported image format, use hdr or exr".to_string()),
+  }
+}
+
+// the most pixels a header may ask for, 16384 by 8192 like the largest environment maps
+// in use, so a corrupt or hostile size fails instead of allocating gigabytes
+const MAX_PIXELS: usize = 16384 * 8192;
+
+// checked before anything is allocated for the pixels
+fn check_size(width: usize, height: usize) -> Result<(), String> {
+  if width == 0 || height == 0 {
+    return Err("image is empty".to_string());
+  }
+  match width.checked_mul(height) {
+    Some(pixels) if pixels <= MAX_PIXELS => Ok(()),
+    _ => Err(format!("image of {width} by {height} pixels is too large")),
+  }
+}
+
+// byte cursor over a whole file, every read past the end is an error
+struct Reader<'a> {
+  bytes   : &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(bytes: &'a [u8]) -> Self {
+    Reader { bytes, position: 0 }
+  }
+
+  fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+    let end = self.position.checked_add(count).filter(|&end| end <= self.bytes.len());
+    let Some(end) = end else {
+      return Err("unexpected end of file".to_string());
+    };
+    let slice = &self.bytes[self.position..end];
+    self.position = end;
+    Ok(slice)
+  }
+
+  fn byte(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn i32(&mut self) -> Result<i32, String> {
+    Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  // bytes up to the next `end` byte, which is consumed
+  fn until(&mut self, end: u8) -> Result<&'a [u8], String> {
+    let rest = &self.bytes[self.position..];
+    let length = rest.iter().position(|&b| b == end).ok_or("unexpected end of file")?;
+    self.position += length + 1;
+    Ok(&rest[..length])
+  }
+}
+
+pub fn read_hdr(bytes: &[u8]) -> Result<RadianceBuffer, String> {
+  let mut reader = Reader::new(bytes);
+  if !reader.until(b'\n')?.starts_with(b"#?") {
+    return Err("not a radiance picture".to_string());
+  }
+  loop {
+    let line = reader.until(b'\n')?;
+    if line.is_empty() {
+      break;
+    }
+    if let Some(format) = line.strip_prefix(b"FORMAT=")
+      && format != b"32-bit_rle_rgbe" {
+      return Err(format!("unsupported pixel format {}", String::from_utf8_lossy(format)));
+    }
+  }
+
+  // only the usual top to bottom, left to right orientation
+  let resolution = String::from_utf8_lossy(reader.until(b'\n')?).into_owned();
+  let size = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+    ["-Y", height, "+X", width] => width.parse::<usize>().ok().zip(height.parse::<usize>().ok()),
+    _ => None,
+  };
+  let Some((width, height)) = size else {
+    return Err(format!("unsupported resolution line \"{resolution}\""));
+  };
+  check_size(width, height)?;
+
+  let mut image = RadianceBuffer::new(width, height);
+  let mut planes = vec![0u8; 4 * width];
+  for row in image.pixels.chunks_mut(width) {
+    let start = reader.position;
+    let marker = reader.take(4).unwrap_or(&[]);
+    let encoded = (8..0x8000).contains(&width)
+      && marker.len() == 4
+      && marker[..2] == [2, 2]
+      && ((marker[2] as usize) << 8 | marker[3] as usize) == width;
+
+    if !encoded {
+      reader.position = start;
+      for pixel in row {
+        *pixel = from_rgbe(reader.take(4)?.try_into().unwrap());
+      }
+      continue;
+    }
+
+    // every component is stored as a separate plane of runs and literal dumps
+    for plane in planes.chunks_mut(width) {
+      let mut i = 0;
+      while i < width {
+        let count = reader.byte()? as usize;
+        let (length, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+        if length == 0 || i + length > width {
+          return Err("corrupt run-length scanline".to_string());
+        }
+        if run {
+          let value = reader.byte()?;
+          plane[i..i + length].fill(value);
+        } else {
+          plane[i..i + length].copy_from_slice(reader.take(length)?);
+        }
+        i += length;
+      }
+    }
+    for (x, pixel) in row.iter_mut().enumerate() {
+      *pixel = from_rgbe([planes[x], planes[width + x], planes[2 * width + x], planes[3 * width + x]]);
+    }
+  }
+
+  Ok(image)
+}
+
+// shared exponent decoding, the mantissas are taken at the middle of their step
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+  if e == 0 {
+    return [0.0; 3];
+  }
+  let scale = 2f32.powi(e as i32 - 136);
+  [r, g, b].map(|v| (v as f32 + 0.5) * scale)
+}
+
+// EXR pixel types
+const EXR_UINT : i32 = 0;
+const EXR_HALF : i32 = 1;
+const EXR_FLOAT: i32 = 2;
+
+struct ExrChannel {
+  name      : String,
+  pixel_type: i32,
+}
+
+impl ExrChannel {
+  fn size(&self) -> usize {
+    if self.pixel_type == EXR_HALF { 2 } else { 4 }
+  }
+}
+
+pub fn read_exr(bytes: &[u8]) -> Result<RadianceBuffer, String> {
+  let mut reader = Reader::new(bytes);
+  if reader.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+    return Err("not an OpenEXR file".to_string());
+  }
+  let version = reader.i32()?;
+  if version & 0x1a00 != 0 {
+    return Err("tiled, deep and multi-part EXR files are not supported".to_string());
+  }
+
+  let mut channels = Vec::new();
+  let mut compression = None;
+  let mut window = None;
+  loop {
+    let name = reader.until(0)?;
+    if name.is_empty() {
+      break;
+    }
+    let kind = reader.until(0)?;
+    let size = reader.i32()?;
+    let value = reader.take(size.max(0) as usize)?;
+
+    match (name, kind) {
+      (b"channels", b"chlist") => {
+        let mut list = Reader::new(value);
+        loop {
+          let name = list.until(0)?;
+          if name.is_empty() {
+            break;
+          }
+          let pixel_type = list.i32()?;
+          list.take(4)?; // pLinear and reserved
+          if (list.i32()?, list.i32()?) != (1, 1) {
+            return Err("subsampled EXR channels are not supported".to_string());
+          }
+          if !matches!(pixel_type, EXR_UINT | EXR_HALF | EXR_FLOAT) {
+            return Err(format!("unknown EXR pixel type {pixel_type}"));
+          }
+          channels.push(ExrChannel { name: String::from_utf8_lossy(name).into_owned(), pixel_type });
+        }
+      }
+      (b"compression", b"compression") => compression = value.first().copied(),
+      (b"dataWindow", b"box2i") => {
+        let mut corners = Reader::new(value);
+        window = Some([corners.i32()?, corners.i32()?, corners.i32()?, corners.i32()?]);
+      }
+      _ => {}
+    }
+  }
+
+  let Some([x_min, y_min, x_max, y_max]) = window else {
+    return Err("missing EXR data window".to_string());
+  };
+  let width  = (x_max as i64 - x_min as i64 + 1).max(0) as usize;
+  let height = (y_max as i64 - y_min as i64 + 1).max(0) as usize;
+  check_size(width, height)?;
+  let lines_per_block = match compression {
+    Some(0..=2) => 1,
+    Some(3) => 16,
+    Some(other) => return Err(format!("unsupported EXR compression {other}, use none, RLE, ZIPS or ZIP")),
+    None => return Err("missing EXR compression".to_string()),
+  };
+
+  // the color channels, gray images only have luminance
+  let find = |name: &str| channels.iter().position(|c| c.name == name);
+  let sources = match (find("R"), find("G"), find("B"), find("Y")) {
+    (Some(r), Some(g), Some(b), _) => [r, g, b],
+    (_, _, _, Some(y)) => [y, y, y],
+    _ => return Err("EXR file has no R, G, B or Y channels".to_string()),
+  };
+
+  // channels are stored one after the other within every scanline
+  let line_size: usize = channels.iter().map(|c| c.size() * width).sum();
+  let mut starts = Vec::with_capacity(channels.len());
+  let mut start = 0;
+  for channel in &channels {
+    starts.push(start);
+    start += channel.size() * width;
+  }
+
+  let blocks = height.div_ceil(lines_per_block);
+  let offsets = (0..blocks).map(|_| reader.u64()).collect::<Result<Vec<_>, _>>()?;
+
+  let mut image = RadianceBuffer::new(width, height);
+  for offset in offsets {
+    reader.position = usize::try_from(offset).map_err(|e| e.to_string())?;
+    let y = reader.i32()? as i64 - y_min as i64;
+    let size = reader.i32()?.max(0) as usize;
+    let data = reader.take(size)?;
+
+    if y < 0 || y as usize >= height {
+      return Err("EXR block outside the data window".to_string());
+    }
+    let y = y as usize;
+    let lines = lines_per_block.min(height - y);
+    let expected = lines * line_size;
+
+    // blocks that would not shrink are stored as they are
+    let data = if size == expected {
+      data.to_vec()
+    } else {
+      let packed = match compression {
+        Some(1) => decode_exr_rle(data, expected)?,
+        Some(2 | 3) => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, expected)
+          .map_err(|e| format!("corrupt EXR block: {e:?}"))?,
+        _ => return Err("EXR block size does not match the image".to_string()),
+      };
+      unpack_exr_block(packed)
+    };
+    if data.len() != expected {
+      return Err("EXR block size does not match the image".to_string());
+    }
+
+    for (line, bytes) in data.chunks_exact(line_size).enumerate() {
+      let row = &mut image.pixels[(y + line) * width..(y + line + 1) * width];
+      for (x, pixel) in row.iter_mut().enumerate() {
+        for (component, &source) in sources.iter().enumerate() {
+          let channel = &channels[source];
+          let at = starts[source] + x * channel.size();
+          pixel[component] = match channel.pixel_type {
+            EXR_HALF  => half_to_f32(u16::from_le_bytes([bytes[at], bytes[at + 1]])),
+            EXR_FLOAT => f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()),
+            _         => u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as f32,
+          };
+        }
+      }
+    }
+  }
+
+  Ok(image)
+}
+
+// signed counts, negative ones are literal dumps and the others repeat the next byte
+// count + 1 times
+fn decode_exr_rle(data: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+  let mut out = Vec::with_capacity(expected);
+  let mut reader = Reader::new(data);
+  while reader.position < data.len() {
+    let count = reader.byte()? as i8;
+    if count < 0 {
+      out.extend_from_slice(reader.take(-(count as i32) as usize)?);
+    } else {
+      let value = reader.byte()?;
+      out.resize(out.len() + count as usize + 1, value);
+    }
+    if out.len() > expected {
+      return Err("corrupt EXR block".to_string());
+    }
+  }
+  Ok(out)
+}
+
+// undoes the byte predictor and the split of even and odd bytes applied before
+// RLE and ZIP compression
+fn unpack_exr_block(mut data: Vec<u8>) -> Vec<u8> {
+  for i in 1..data.len() {
+    data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+  }
+  let half = data.len().div_ceil(2);
+  let mut out = Vec::with_capacity(data.len());
+  for i in 0..half {
+    out.push(data[i]);
+    if half + i < data.len() {
+      out.push(data[half + i]);
+    }
+  }
+  out
+}
+
+// IEEE 754 binary16 to single precision, exact for every value
+fn half_to_f32(half: u16) -> f32 {
+  let sign = ((half & 0x8000) as u32) << 16;
+  let exponent = ((half >> 10) & 0x1f) as u32;
+  let mantissa = (half & 0x03ff) as u32;
+  match exponent {
+    // subnormal, mantissa * 2^-24
+    0 => {
+      let value = mantissa as f32 / (1 << 24) as f32;
+      if sign != 0 { -value } else { value }
+    }
+    0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+    _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::camera::Camera;
+  use crate::math::Vec3;
+  use crate::output::{ write_exr, write_hdr, ExrPrecision, Frame };
+
+  // long runs for the run-length schemes, values that half floats hold exactly,
+  // including a subnormal one
+  fn test_image(width: usize, height: usize) -> RadianceBuffer {
+    let mut image = RadianceBuffer::new(width, height);
+    for (i, pixel) in image.pixels.iter_mut().enumerate() {
+      *pixel = match i % 11 {
+        0 => [0.25, 2.0, 1024.0],
+        1 => [3.0, 0.0, 2f32.powi(-20)],
+        _ => [0.5, 1.0, 1.5],
+      };
+    }
+    image
+  }
+
+  fn encode(radiance: RadianceBuffer, write: impl Fn(&mut Vec<u8>, &Frame)) -> Vec<u8> {
+    let camera = Camera::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), 60.0, 1.0, radiance.width);
+    let frame = Frame { camera: &camera, radiance, aovs: Vec::new() };
+    let mut bytes = Vec::new();
+    write(&mut bytes, &frame);
+    bytes
+  }
+
+  #[test]
+  fn exr_float_round_trip() {
+    let image = test_image(16, 3);
+    let bytes = encode(test_image(16, 3), |out, frame| write_exr(out, frame, ExrPrecision::Float).unwrap());
+    let read = read_exr(&bytes).unwrap();
+    assert_eq!((read.width, read.height), (16, 3));
+    assert_eq!(read.pixels, image.pixels);
+  }
+
+  #[test]
+  fn exr_half_round_trip() {
+    let image = test_image(16, 3);
+    let bytes = encode(test_image(16, 3), |out, frame| write_exr(out, frame, ExrPrecision::Half).unwrap());
+    assert_eq!(read_exr(&bytes).unwrap().pixels, image.pixels);
+  }
+
+  // rewrites the uncompressed file of `write_exr` with `compression`, blocks that do
+  // not shrink would be stored as they are
+  fn compress_exr(bytes: &[u8], height: usize, compression: u8, pack: impl Fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+    let mut reader = Reader::new(bytes);
+    reader.take(8).unwrap();
+    while !reader.until(0).unwrap().is_empty() {
+      reader.until(0).unwrap();
+      let size = reader.i32().unwrap() as usize;
+      reader.take(size).unwrap();
+    }
+    let first_block = reader.position + 8 * height;
+    let mut header = bytes[..reader.position].to_vec();
+    let attribute = b"compression\0compression\0\x01\0\0\0";
+    let at = header.windows(attribute.len()).position(|w| w == attribute).unwrap();
+    header[at + attribute.len()] = compression;
+
+    let mut lines = Vec::new();
+    reader.position = first_block;
+    for _ in 0..height {
+      reader.i32().unwrap();
+      let size = reader.i32().unwrap() as usize;
+      lines.push(reader.take(size).unwrap());
+    }
+
+    let lines_per_block = if compression == 3 { 16 } else { 1 };
+    let mut blocks = Vec::new();
+    for (index, group) in lines.chunks(lines_per_block).enumerate() {
+      let packed = pack(&group.concat());
+      assert!(packed.len() < group.concat().len());
+      blocks.push(((index * lines_per_block) as i32, packed));
+    }
+
+    let mut out = header;
+    let mut offset = out.len() + 8 * blocks.len();
+    for (_, data) in &blocks {
+      out.extend_from_slice(&(offset as u64).to_le_bytes());
+      offset += 8 + data.len();
+    }
+    for (y, data) in &blocks {
+      out.extend_from_slice(&y.to_le_bytes());
+      out.extend_from_slice(&(data.len() as i32).to_le_bytes());
+      out.extend_from_slice(data);
+    }
+    out
+  }
+
+  // the inverse of `unpack_exr_block`
+  fn pack_exr_block(data: &[u8]) -> Vec<u8> {
+    let mut split: Vec<u8> = data.iter().step_by(2).chain(data.iter().skip(1).step_by(2)).copied().collect();
+    for i in (1..split.len()).rev() {
+      split[i] = split[i].wrapping_sub(split[i - 1]).wrapping_add(128);
+    }
+    split
+  }
+
+  fn encode_exr_rle(data: &[u8]) -> Vec<u8> {
+    let run_at = |i: usize| data[i..].iter().take(128).take_while(|&&b| b == data[i]).count();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+      let run = run_at(i);
+      if run >= 3 {
+        out.extend_from_slice(&[(run - 1) as u8, data[i]]);
+        i += run;
+        continue;
+      }
+      let start = i;
+      while i < data.len() && i - start < 128 && run_at(i) < 3 {
+        i += 1;
+      }
+      out.push((-((i - start) as i32)) as u8);
+      out.extend_from_slice(&data[start..i]);
+    }
+    out
+  }
+
+  // `write_exr` output with the data window replaced
+  fn with_exr_window(bytes: &[u8], window: [i32; 4]) -> Vec<u8> {
+    let attribute = b"dataWindow\0box2i\0\x10\0\0\0";
+    let at = bytes.windows(attribute.len()).position(|w| w == attribute).unwrap() + attribute.len();
+    let mut bytes = bytes.to_vec();
+    for (i, corner) in window.iter().enumerate() {
+      bytes[at + 4 * i..at + 4 * i + 4].copy_from_slice(&corner.to_le_bytes());
+    }
+    bytes
+  }
+
+  #[test]
+  fn exr_window_size() {
+    let bytes = encode(test_image(16, 3), |out, frame| write_exr(out, frame, ExrPrecision::Float).unwrap());
+    assert_eq!(read_exr(&with_exr_window(&bytes, [0, 0, 15, 2])).unwrap().pixels, test_image(16, 3).pixels);
+    assert_eq!(read_exr(&with_exr_window(&bytes, [0, 0, -1, 2])).err().unwrap(), "image is empty");
+    assert_eq!(read_exr(&with_exr_window(&bytes, [5, 0, 2, 2])).err().unwrap(), "image is empty");
+    assert_eq!(
+      read_exr(&with_exr_window(&bytes, [i32::MIN, i32::MIN, i32::MAX, i32::MAX])).err().unwrap(),
+      "image of 4294967296 by 4294967296 pixels is too large",
+    );
+  }
+
+  #[test]
+  fn exr_compressed_blocks() {
+    let image = test_image(16, 20);
+    for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+      let bytes = encode(test_image(16, 20), |out, frame| write_exr(out, frame, precision).unwrap());
+      let rle  = compress_exr(&bytes, 20, 1, |data| encode_exr_rle(&pack_exr_block(data)));
+      let zips = compress_exr(&bytes, 20, 2, |data| miniz_oxide::deflate::compress_to_vec_zlib(&pack_exr_block(data), 6));
+      let zip  = compress_exr(&bytes, 20, 3, |data| miniz_oxide::deflate::compress_to_vec_zlib(&pack_exr_block(data), 6));
+      for file in [rle, zips, zip] {
+        assert_eq!(read_exr(&file).unwrap().pixels, image.pixels);
+      }
+    }
+  }
+
+  #[test]
+  fn exr_block_transforms() {
+    let data: Vec<u8> = (0..37).map(|i: u32| (i * i % 7) as u8).collect();
+    assert_eq!(unpack_exr_block(pack_exr_block(&data)), data);
+    assert_eq!(decode_exr_rle(&encode_exr_rle(&data), data.len()).unwrap(), data);
+    assert!(decode_exr_rle(&[5, 0], 4).is_err());
+    assert!(decode_exr_rle(&[(-3i8) as u8, 1], 3).is_err());
+  }
+
+  #[test]
+  fn half_to_f32_special_values() {
+    assert_eq!(half_to_f32(0x3c00), 1.0);
+    assert_eq!(half_to_f32(0xc000), -2.0);
+    assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+    assert_eq!(half_to_f32(0x8000).to_bits(), (-0.0f32).to_bits());
+    assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+    assert!(half_to_f32(0x7e00).is_nan());
+  }
+
+  #[test]
+  fn hdr_round_trip() {
+    let image = test_image(16, 3);
+    let read = read_hdr(&encode(test_image(16, 3), |out, frame| write_hdr(out, frame).unwrap())).unwrap();
+    assert_eq!((read.width, read.height), (16, 3));
+    for (read, original) in read.pixels.iter().zip(&image.pixels) {
+      // the shared exponent gives every component the step of the brightest one
+      let peak = original.iter().fold(0.0f32, |a, &b| a.max(b));
+      for (r, o) in read.iter().zip(original) {
+        assert!((r - o).abs() <= peak / 128.0);
+      }
+    }
+  }
+
+  #[test]
+  fn hdr_rle_matches_flat() {
+    // the same pixels written 16 wide are run-length encoded, 4 wide they are flat
+    let rle  = encode(test_image(16, 3), |out, frame| write_hdr(out, frame).unwrap());
+    let flat = encode(test_image(4, 12), |out, frame| write_hdr(out, frame).unwrap());
+    assert!(rle.len() < flat.len());
+    assert_eq!(read_hdr(&rle).unwrap().pixels, read_hdr(&flat).unwrap().pixels);
+  }
+
+  #[test]
+  fn hdr_resolution_size() {
+    let header = |resolution: &str| format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{resolution}\n").into_bytes();
+    assert_eq!(read_hdr(&header("-Y 4 +X 0")).err().unwrap(), "image is empty");
+    assert_eq!(read_hdr(&header("-Y 0 +X 4")).err().unwrap(), "image is empty");
+    assert_eq!(
+      read_hdr(&header("-Y 100000 +X 100000")).err().unwrap(),
+      "image of 100000 by 100000 pixels is too large",
+    );
+    assert!(read_hdr(&header(&format!("-Y {} +X 2", usize::MAX))).err().unwrap().ends_with("is too large"));
+  }
+}
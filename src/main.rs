@@ -1,3 +1,4 @@
+mod aov;
 mod math;
 mod color;
 mod ray;
@@ -5,26 +6,123 @@ mod camera;
 mod scene;
 mod framebuffer;
 mod utils;
+mod sampler;
+mod output;
+mod cli;
+mod viewer;
+mod tonemap;
+mod input;
 
+use std::path::Path;
+use std::process;
 use std::sync::Arc;
 
-use minifb::{Key, Window, WindowOptions};
 use crate::math::Vec3;
 use crate::color::Color;
 use crate::math::Point3;
 use crate::ray::Ray;
 use crate::camera::Camera;
-use crate::scene::{ Scene, AmbientLight, object::{ Object, Sphere, Cylinder, Plane } };
+use crate::scene::{ Scene, AmbientLight, object::{ Object, Sphere } };
+use crate::cli::{ Command, RenderOptions };
+use crate::aov::Aov;
+use crate::scene::integrator::PathTracer;
 
-use self::scene::material::{BasicMetal, Solid};
+use self::scene::material::BasicMetal;
+use self::scene::texture::constant;
+
+// process exit codes, also listed in `cli::USAGE`
+const EXIT_USAGE : i32 = 2;
+const EXIT_SCENE : i32 = 3;
+const EXIT_OUTPUT: i32 = 4;
 
 fn main() {
+  let command = cli::parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+    eprintln!("error: {e}\n\n{}", cli::USAGE);
+    process::exit(EXIT_USAGE);
+  });
+
+  match command {
+    Command::Help => println!("{}", cli::USAGE),
+    Command::Window { scene } => {
+      let path = scene.as_deref();
+      viewer::run(load_scene(path, cli::DEFAULT_WIDTH, cli::DEFAULT_ASPECT_RATIO), path);
+    }
+    Command::Render(options) => run_render(&options),
+  }
+}
+
+// loads the `.rt` or glTF file at `path`, or builds the demo scene when there is none
+fn load_scene(path: Option<&Path>, image_width: usize, aspect_ratio: f64) -> Scene {
+  let Some(path) = path else {
+    return demo_scene(image_width, aspect_ratio);
+  };
+
+  scene::load_file(path, image_width, aspect_ratio).unwrap_or_else(|e| {
+    eprintln!("{}: {e}", path.display());
+    process::exit(EXIT_SCENE);
+  })
+}
+
+// renders a single frame and writes it to disk
+fn run_render(options: &RenderOptions) {
+  let mut scene = load_scene(options.scene.as_deref(), options.width, options.aspect_ratio());
+  if let Some(spp) = options.spp {
+    scene.camera.sampling_rate = spp;
+  }
+  if let Some(depth) = options.depth {
+    scene.integrator = Box::new(PathTracer { roulette_depth: depth });
+  }
+  if let Some(threads) = options.threads {
+    scene.camera.threads = threads;
+  }
+  scene.camera.seed = options.seed;
+  if let Some(sampler) = options.sampler {
+    scene.camera.sampler = sampler;
+  }
+  scene.camera.adaptive = options.adaptive;
+
+  // the heatmap is drawn from the sample count pass, rendered even when not written out
+  let mut requested = options.aovs.clone();
+  if options.heatmap.is_some() && !requested.contains(&Aov::Samples) {
+    requested.push(Aov::Samples);
+  }
+
+  let (radiance, mut aovs) = scene.render_with_aovs(&requested, 0);
+  if options.stats {
+    eprintln!("bvh: {}", scene.bvh_stats());
+  }
+
+  if let Some((path, format)) = &options.heatmap {
+    let counts = aovs.iter().find(|pass| pass.aov == Aov::Samples).expect("sample count pass");
+    let (width, height) = (radiance.width, radiance.height);
+    if let Err(e) = output::write_heatmap(path, *format, &counts.pixels, width, height) {
+      eprintln!("{}: {e}", path.display());
+      process::exit(EXIT_OUTPUT);
+    }
+  }
+  aovs.retain(|pass| options.aovs.contains(&pass.aov));
+
+  let frame = output::Frame {
+    camera: &scene.camera,
+    radiance,
+    aovs,
+  };
+  if let Err(e) = output::write_image(&options.output, options.format, &frame, &options.display) {
+    eprintln!("{}: {e}", options.output.display());
+    process::exit(EXIT_OUTPUT);
+  }
+}
+
+// hard-coded scene shown when no `.rt` file is given
+fn demo_scene(image_width: usize, aspect_ratio: f64) -> Scene {
   let mut scene = Scene::new(
-    Camera::new(
+    Camera::look_at(
       Point3::new(0.0, 0.0, 0.0), 
+      Point3::new(0.0, 0.0, -2.5),
+      Vec3::new(0.0, 1.0, 0.0),
       45.0, 
-      16.0 / 9.0,
-      1280,
+      aspect_ratio,
+      image_width,
     ),
     AmbientLight { 
       ratio: 0.2,
@@ -37,7 +135,7 @@ fn main() {
     radius: 0.5,
     material: Arc::new( 
       BasicMetal {
-        albedo: Color::rgb(0.8, 0.8, 0.8),
+        albedo: constant(Color::rgb(0.8, 0.8, 0.8)),
         fuzz: 0.05,
       }
     ),
@@ -48,7 +146,7 @@ fn main() {
     radius: 0.5,
     material: Arc::new(
       BasicMetal {
-        albedo: Color::rgb(0.8, 0.6, 0.2),
+        albedo: constant(Color::rgb(0.8, 0.6, 0.2)),
         fuzz: 0.05,
       }
     ), 
@@ -59,7 +157,7 @@ fn main() {
     radius: 100.0,
     material: Arc::new(
       BasicMetal {
-        albedo: Color::rgb(0.8, 0.8, 0.0),
+        albedo: constant(Color::rgb(0.8, 0.8, 0.0)),
         fuzz: 0.05,
       }
     ),
@@ -70,7 +168,7 @@ fn main() {
     radius: 0.5,
     material: Arc::new(
       BasicMetal {
-        albedo: Color::rgb(0.1, 0.2, 0.5),
+        albedo: constant(Color::rgb(0.1, 0.2, 0.5)),
         fuzz: 0.5,
       }
     ),
@@ -80,26 +178,5 @@ fn main() {
   scene.add_object(Object::Sphere(sp2));
   scene.add_object(Object::Sphere(sp3));
   scene.add_object(Object::Sphere(sp4));
-  let camera = &scene.camera;
-  
-  // window setup
-  let mut window = Window::new(
-    "Test",
-    camera.image_width,
-    camera.image_height, 
-    WindowOptions {
-      resize: true,
-      ..WindowOptions::default()
-  })
-  .unwrap_or_else(|e| {
-      panic!("{}", e);
-  });
-  window.set_target_fps(60);
-  
- 
-  while window.is_open() && !window.is_key_down(Key::Escape) {
-    let buffer = scene.render_frame();
-    window.update_with_buffer(&buffer.buf, camera.image_width, camera.image_height).unwrap();
-  }
+  scene
 }
-
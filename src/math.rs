@@ -1,9 +1,9 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
-use crate::{color::Color, utils::random_double_in};
+use crate::color::Color;
 
 pub const EPSILON: f64 = 1e-8;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Default)]
 pub struct Vec3 {
   pub x: f64,
   pub y: f64,
@@ -22,7 +22,7 @@ impl Vec3 {
   }
   
   pub fn length(&self) -> f64 {
-    return self.length_squared().sqrt();
+    self.length_squared().sqrt()
   }
   
   pub fn length_squared(&self) -> f64 {
@@ -47,18 +47,55 @@ impl Vec3 {
     *self / self.length()
   }
 
-  pub fn random_unit_sphere() -> Vec3 {
-    loop {
-      let v = Vec3::new(
-        random_double_in(-1.0, 1.0),
-        random_double_in(-1.0, 1.0),
-        random_double_in(-1.0, 1.0)
-      );
-      let lensq = v.length_squared();
-      if lensq < 1.0 && lensq > 1e-160 {
-        return v.unit();
-      }
-    }  
+  // mirror direction around the normal `n`
+  pub fn reflect(&self, n: &Vec3) -> Vec3 {
+    *self - 2.0 * self.dot(n) * *n
+  }
+
+  // snell's law for a unit vector entering a surface with unit normal `n` facing it,
+  // `eta` is the ratio of the refractive indices (incident over transmitted)
+  pub fn refract(&self, n: &Vec3, eta: f64) -> Vec3 {
+    let cos_theta = (-*self).dot(n).min(1.0);
+    let perpendicular = eta * (*self + cos_theta * *n);
+    let parallel = -(1.0 - perpendicular.length_squared()).abs().sqrt() * *n;
+    perpendicular + parallel
+  }
+
+  pub fn clamp(&self, min: f64, max: f64) -> Vec3 {
+    Vec3::new(
+      self.x.clamp(min, max),
+      self.y.clamp(min, max),
+      self.z.clamp(min, max),
+    )
+  }
+
+  // two unit vectors completing this unit vector to an orthonormal basis (duff et al.)
+  pub fn basis(&self) -> (Vec3, Vec3) {
+    let sign = 1.0_f64.copysign(self.z);
+    let a = -1.0 / (sign + self.z);
+    let b = self.x * self.y * a;
+    (
+      Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+      Vec3::new(b, sign + self.y * self.y * a, -self.y),
+    )
+  }
+
+  // maps a uniform sample of the unit square to a direction in the hemisphere around
+  // the unit vector `normal`, with a density proportional to the cosine to it
+  pub fn cosine_hemisphere(normal: &Vec3, (s, t): (f64, f64)) -> Vec3 {
+    let r   = s.sqrt();
+    let phi = 2.0 * std::f64::consts::PI * t;
+    let (tangent, bitangent) = normal.basis();
+    let z = (1.0 - s).max(0.0).sqrt();
+    (r * phi.cos() * tangent + r * phi.sin() * bitangent + z * *normal).unit()
+  }
+
+  // maps a uniform sample of the unit square to a uniform direction
+  pub fn on_unit_sphere((s, t): (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * s;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * t;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
   }
 }
 
@@ -230,3 +267,210 @@ impl DivAssign<f64> for Vec3 {
     *self *= 1.0 / scalar
   }
 }
+
+pub type Matrix = [[f64; 4]; 4];
+
+// affine transform as a row-major 4x4 matrix acting on column vectors,
+// the inverse is kept alongside so nothing has to be inverted per ray
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+  pub matrix : Matrix,
+  pub inverse: Matrix,
+}
+
+const IDENTITY: Matrix = [
+  [1.0, 0.0, 0.0, 0.0],
+  [0.0, 1.0, 0.0, 0.0],
+  [0.0, 0.0, 1.0, 0.0],
+  [0.0, 0.0, 0.0, 1.0],
+];
+
+impl Transform {
+  pub fn identity() -> Self {
+    Transform { matrix: IDENTITY, inverse: IDENTITY }
+  }
+
+  pub fn translate(offset: Vec3) -> Self {
+    let mut matrix  = IDENTITY;
+    let mut inverse = IDENTITY;
+    for i in 0..3 {
+      matrix[i][3]  = offset[i];
+      inverse[i][3] = -offset[i];
+    }
+    Transform { matrix, inverse }
+  }
+
+  // every factor must be non-zero
+  pub fn scale(factors: Vec3) -> Self {
+    let mut matrix  = IDENTITY;
+    let mut inverse = IDENTITY;
+    for i in 0..3 {
+      matrix[i][i]  = factors[i];
+      inverse[i][i] = 1.0 / factors[i];
+    }
+    Transform { matrix, inverse }
+  }
+
+  // counter-clockwise rotation around `axis` when looking down the axis towards the origin
+  pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+    let a = axis.unit();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let k = 1.0 - cos;
+
+    // rodrigues' formula
+    let rotation = [
+      [a.x * a.x * k + cos,       a.x * a.y * k - a.z * sin, a.x * a.z * k + a.y * sin],
+      [a.y * a.x * k + a.z * sin, a.y * a.y * k + cos,       a.y * a.z * k - a.x * sin],
+      [a.z * a.x * k - a.y * sin, a.z * a.y * k + a.x * sin, a.z * a.z * k + cos      ],
+    ];
+
+    // rotations are orthogonal, the inverse is the transpose
+    let mut matrix  = IDENTITY;
+    let mut inverse = IDENTITY;
+    for i in 0..3 {
+      for j in 0..3 {
+        matrix[i][j]  = rotation[i][j];
+        inverse[j][i] = rotation[i][j];
+      }
+    }
+    Transform { matrix, inverse }
+  }
+
+  // affine transform from its matrix, the last row is taken as (0, 0, 0, 1),
+  // None when the matrix cannot be inverted
+  pub fn from_matrix(matrix: Matrix) -> Option<Self> {
+    let m = &matrix;
+    // inverse of the linear part from its cofactors
+    let cofactors = [
+      [m[1][1] * m[2][2] - m[1][2] * m[2][1], m[1][2] * m[2][0] - m[1][0] * m[2][2], m[1][0] * m[2][1] - m[1][1] * m[2][0]],
+      [m[0][2] * m[2][1] - m[0][1] * m[2][2], m[0][0] * m[2][2] - m[0][2] * m[2][0], m[0][1] * m[2][0] - m[0][0] * m[2][1]],
+      [m[0][1] * m[1][2] - m[0][2] * m[1][1], m[0][2] * m[1][0] - m[0][0] * m[1][2], m[0][0] * m[1][1] - m[0][1] * m[1][0]],
+    ];
+    let determinant = m[0][0] * cofactors[0][0] + m[0][1] * cofactors[0][1] + m[0][2] * cofactors[0][2];
+    if determinant == 0.0 || !determinant.is_finite() {
+      return None;
+    }
+
+    let mut matrix  = matrix;
+    let mut inverse = IDENTITY;
+    matrix[3] = IDENTITY[3];
+    for i in 0..3 {
+      for j in 0..3 {
+        inverse[i][j] = cofactors[j][i] / determinant;
+      }
+    }
+    // the inverse translation undoes the original one after the inverse linear part
+    for row in inverse.iter_mut().take(3) {
+      row[3] = -(0..3).map(|j| row[j] * matrix[j][3]).sum::<f64>();
+    }
+    Some(Transform { matrix, inverse })
+  }
+
+  // applies `self` first and `next` after it
+  pub fn then(&self, next: &Transform) -> Transform {
+    Transform {
+      matrix : multiply(&next.matrix, &self.matrix),
+      inverse: multiply(&self.inverse, &next.inverse),
+    }
+  }
+
+  pub fn inverted(&self) -> Transform {
+    Transform { matrix: self.inverse, inverse: self.matrix }
+  }
+
+  pub fn point(&self, p: Point3) -> Point3 {
+    let m = &self.matrix;
+    Point3::new(
+      m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+      m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+      m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+    )
+  }
+
+  // directions ignore the translation
+  pub fn vector(&self, v: Vec3) -> Vec3 {
+    let m = &self.matrix;
+    Vec3::new(
+      m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+      m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+      m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+  }
+
+  // normals go through the inverse transpose to stay perpendicular to the surface,
+  // the result is not normalized
+  pub fn normal(&self, n: Vec3) -> Vec3 {
+    let m = &self.inverse;
+    Vec3::new(
+      m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+      m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+      m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+    )
+  }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+  let mut result = [[0.0; 4]; 4];
+  for (i, row) in result.iter_mut().enumerate() {
+    for (j, value) in row.iter_mut().enumerate() {
+      *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_matrix_eq(a: &Matrix, b: &Matrix) {
+    for (row_a, row_b) in a.iter().zip(b) {
+      for (x, y) in row_a.iter().zip(row_b) {
+        assert!((x - y).abs() < 1e-9, "{a:?} != {b:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn from_matrix_inverts() {
+    let matrix = [
+      [2.0, 0.5, -1.0, 3.0],
+      [0.0, 1.5,  0.3, -2.0],
+      [1.0, 0.0,  4.0, 0.5],
+      [0.0, 0.0,  0.0, 1.0],
+    ];
+    let transform = Transform::from_matrix(matrix).unwrap();
+    assert_matrix_eq(&multiply(&transform.matrix, &transform.inverse), &IDENTITY);
+    assert_matrix_eq(&multiply(&transform.inverse, &transform.matrix), &IDENTITY);
+
+    let p = Point3::new(0.3, -1.2, 5.0);
+    let back = transform.inverted().point(transform.point(p));
+    assert!((back - p).length() < 1e-9);
+  }
+
+  #[test]
+  fn from_matrix_matches_composed_transforms() {
+    let composed = Transform::scale(Vec3::new(2.0, -1.0, 0.5))
+      .then(&Transform::rotate(Vec3::new(1.0, 2.0, -1.0), 35.0))
+      .then(&Transform::translate(Vec3::new(4.0, -3.0, 2.0)));
+    let transform = Transform::from_matrix(composed.matrix).unwrap();
+    assert_matrix_eq(&transform.matrix, &composed.matrix);
+    assert_matrix_eq(&transform.inverse, &composed.inverse);
+  }
+
+  #[test]
+  fn from_matrix_rejects_singular_matrices() {
+    let mut flat = IDENTITY;
+    flat[1][1] = 0.0;
+    assert!(Transform::from_matrix(flat).is_none());
+
+    let mut infinite = IDENTITY;
+    infinite[0][0] = f64::INFINITY;
+    assert!(Transform::from_matrix(infinite).is_none());
+
+    // the projective row is ignored
+    let mut projective = IDENTITY;
+    projective[3] = [1.0, 2.0, 3.0, 4.0];
+    assert_eq!(Transform::from_matrix(projective).unwrap(), Transform::identity());
+  }
+}
@@ -0,0 +1,382 @@
+// writers for rendered frames
+//
+// png and ppm hold tone mapped 8-bit sRGB, exr and hdr keep the linear radiance
+// together with the camera that produced it
+
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+
+use crate::aov::AovBuffer;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::framebuffer::{ FrameBuffer, RadianceBuffer };
+use crate::math::Vec3;
+use crate::tonemap::DisplayTransform;
+
+// storage of EXR channel values
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ExrPrecision {
+  Half,
+  #[default]
+  Float,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+  Png,
+  Ppm,
+  Exr(ExrPrecision),
+  Hdr,
+}
+
+impl ImageFormat {
+  // picks the format from the file extension
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+      "png" => Some(ImageFormat::Png),
+      "ppm" => Some(ImageFormat::Ppm),
+      "exr" => Some(ImageFormat::Exr(ExrPrecision::default())),
+      "hdr" => Some(ImageFormat::Hdr),
+      _ => None,
+    }
+  }
+}
+
+// a rendered image and everything written alongside it
+pub struct Frame<'a> {
+  pub camera  : &'a Camera,
+  pub radiance: RadianceBuffer,
+  // extra passes, only multi-layer EXR files store them
+  pub aovs    : Vec<AovBuffer>,
+}
+
+pub fn write_image(path: &Path, format: ImageFormat, frame: &Frame, display: &DisplayTransform) -> io::Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  match format {
+    ImageFormat::Png => write_png(&mut out, &display.encode(&frame.radiance))?,
+    ImageFormat::Ppm => write_ppm(&mut out, &display.encode(&frame.radiance))?,
+    ImageFormat::Exr(precision) => write_exr(&mut out, frame, precision)?,
+    ImageFormat::Hdr => write_hdr(&mut out, frame)?,
+  }
+  out.flush()
+}
+
+// false color image of the samples taken per pixel, from the fewest in dark purple
+// to the most in yellow, `counts` holds one count per pixel in the first component
+pub fn write_heatmap(path: &Path, format: ImageFormat, counts: &[[f32; 3]], width: usize, height: usize) -> io::Result<()> {
+  // viridis, sampled at even steps
+  const STOPS: [[f64; 3]; 5] = [
+    [0.267, 0.005, 0.329],
+    [0.229, 0.322, 0.546],
+    [0.128, 0.567, 0.551],
+    [0.369, 0.789, 0.383],
+    [0.993, 0.906, 0.144],
+  ];
+
+  let (min, max) = counts
+    .iter()
+    .fold((f32::INFINITY, 0.0f32), |(min, max), c| (min.min(c[0]), max.max(c[0])));
+  let range = (max - min).max(1.0);
+
+  let mut buffer = FrameBuffer::new(width, height);
+  for (pixel, count) in buffer.buf.iter_mut().zip(counts) {
+    let position = ((count[0] - min) / range) as f64 * (STOPS.len() - 1) as f64;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f64;
+    let (a, b) = (STOPS[index], STOPS[index + 1]);
+    let mix = |k: usize| a[k] + (b[k] - a[k]) * t;
+    *pixel = Color::rgb(mix(0), mix(1), mix(2)).to_rgb_bytes();
+  }
+
+  let mut out = BufWriter::new(File::create(path)?);
+  match format {
+    ImageFormat::Png => write_png(&mut out, &buffer)?,
+    ImageFormat::Ppm => write_ppm(&mut out, &buffer)?,
+    _ => return Err(io::Error::other("heatmaps are written as png or ppm")),
+  }
+  out.flush()
+}
+
+// splits a packed 0x00RRGGBB pixel into its channels
+fn rgb_bytes(pixel: u32) -> [u8; 3] {
+  [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]
+}
+
+pub fn write_png<W: Write>(out: W, buffer: &FrameBuffer) -> io::Result<()> {
+  let mut encoder = png::Encoder::new(out, buffer.width as u32, buffer.height as u32);
+  encoder.set_color(png::ColorType::Rgb);
+  encoder.set_depth(png::BitDepth::Eight);
+  encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+  let data: Vec<u8> = buffer.buf.iter().flat_map(|&p| rgb_bytes(p)).collect();
+  let mut writer = encoder.write_header().map_err(io::Error::other)?;
+  writer.write_image_data(&data).map_err(io::Error::other)?;
+  writer.finish().map_err(io::Error::other)
+}
+
+// binary P6 pixmap
+pub fn write_ppm<W: Write>(mut out: W, buffer: &FrameBuffer) -> io::Result<()> {
+  write!(out, "P6\n{} {}\n255\n", buffer.width, buffer.height)?;
+  for &pixel in &buffer.buf {
+    out.write_all(&rgb_bytes(pixel))?;
+  }
+  Ok(())
+}
+
+// uncompressed scanline OpenEXR, the radiance goes in the default R, G, B channels
+// and every pass in its own layer, such as `normal.X` or `depth.Z`
+pub fn write_exr<W: Write>(mut out: W, frame: &Frame, precision: ExrPrecision) -> io::Result<()> {
+  let (width, height) = (frame.radiance.width, frame.radiance.height);
+  let (pixel_type, value_size): (i32, usize) = match precision {
+    ExrPrecision::Half  => (1, 2),
+    ExrPrecision::Float => (2, 4),
+  };
+
+  // (channel name, pixels, component), readers expect them sorted by name
+  let mut channels: Vec<(String, &[[f32; 3]], usize)> = Vec::new();
+  for (component, name) in ["R", "G", "B"].iter().enumerate() {
+    channels.push((name.to_string(), &frame.radiance.pixels, component));
+  }
+  for pass in &frame.aovs {
+    for (component, name) in pass.aov.channels().iter().enumerate() {
+      channels.push((format!("{}.{name}", pass.aov.layer()), &pass.pixels, component));
+    }
+  }
+  channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+  let mut header = Vec::new();
+  header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+  header.extend_from_slice(&2i32.to_le_bytes());
+
+  let mut chlist = Vec::new();
+  for (name, _, _) in &channels {
+    chlist.extend_from_slice(name.as_bytes());
+    chlist.push(0);
+    chlist.extend_from_slice(&pixel_type.to_le_bytes());
+    chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+    chlist.extend_from_slice(&1i32.to_le_bytes());
+    chlist.extend_from_slice(&1i32.to_le_bytes());
+  }
+  chlist.push(0);
+  exr_attribute(&mut header, "channels", "chlist", &chlist);
+
+  exr_attribute(&mut header, "compression", "compression", &[0]);
+  let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+    .iter()
+    .flat_map(|v| v.to_le_bytes())
+    .collect();
+  exr_attribute(&mut header, "dataWindow", "box2i", &window);
+  exr_attribute(&mut header, "displayWindow", "box2i", &window);
+  exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+  exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+  exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+  exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+  exr_camera_attributes(&mut header, frame.camera);
+  header.push(0);
+  out.write_all(&header)?;
+
+  // one scanline per block: y, byte count, then every channel's values for the line
+  let line_size = width * channels.len() * value_size;
+  let block_size = 8 + line_size;
+  let first_block = header.len() + 8 * height;
+  for y in 0..height {
+    out.write_all(&((first_block + y * block_size) as u64).to_le_bytes())?;
+  }
+
+  let mut line = Vec::with_capacity(line_size);
+  for y in 0..height {
+    line.clear();
+    for (_, pixels, component) in &channels {
+      for pixel in &pixels[y * width..(y + 1) * width] {
+        let value = pixel[*component];
+        match precision {
+          ExrPrecision::Half  => line.extend_from_slice(&f32_to_half(value).to_le_bytes()),
+          ExrPrecision::Float => line.extend_from_slice(&value.to_le_bytes()),
+        }
+      }
+    }
+    out.write_all(&(y as i32).to_le_bytes())?;
+    out.write_all(&(line_size as i32).to_le_bytes())?;
+    out.write_all(&line)?;
+  }
+
+  Ok(())
+}
+
+// the standard worldToCamera matrix plus the lens settings the standard has no names for
+fn exr_camera_attributes(header: &mut Vec<u8>, camera: &Camera) {
+  let floats = |values: &[f64]| -> Vec<u8> {
+    values.iter().flat_map(|&v| (v as f32).to_le_bytes()).collect()
+  };
+  let vector = |v: Vec3| floats(&[v.x, v.y, v.z]);
+
+  // row vector convention, rows are the images of the world axes followed by the translation
+  let (u, v, w, p) = (camera.basis.u, camera.basis.v, camera.basis.w, camera.position);
+  let world_to_camera = floats(&[
+    u.x, v.x, w.x, 0.0,
+    u.y, v.y, w.y, 0.0,
+    u.z, v.z, w.z, 0.0,
+    -p.dot(&u), -p.dot(&v), -p.dot(&w), 1.0,
+  ]);
+  exr_attribute(header, "worldToCamera", "m44f", &world_to_camera);
+
+  exr_attribute(header, "cameraPosition", "v3f", &vector(camera.position));
+  exr_attribute(header, "cameraDirection", "v3f", &vector(camera.direction));
+  exr_attribute(header, "cameraUp", "v3f", &vector(camera.basis.v));
+  exr_attribute(header, "verticalFov", "float", &floats(&[camera.fov_degrees]));
+  exr_attribute(header, "focusDistance", "float", &floats(&[camera.focus_distance]));
+  exr_attribute(header, "aperture", "float", &floats(&[camera.aperture]));
+  exr_attribute(header, "apertureBlades", "int", &(camera.aperture_blades as i32).to_le_bytes());
+  exr_attribute(header, "samplesPerPixel", "int", &(camera.sampling_rate as i32).to_le_bytes());
+
+  exr_attribute(header, "software", "string", b"raytreizer");
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+  header.extend_from_slice(name.as_bytes());
+  header.push(0);
+  header.extend_from_slice(kind.as_bytes());
+  header.push(0);
+  header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+  header.extend_from_slice(value);
+}
+
+// IEEE 754 binary16 with round half up, out of range values become infinity
+fn f32_to_half(value: f32) -> u16 {
+  let bits = value.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exponent = ((bits >> 23) & 0xff) as i32;
+  let mantissa = bits & 0x007f_ffff;
+
+  if exponent == 0xff {
+    let nan = if mantissa != 0 { 0x0200 } else { 0 };
+    return sign | 0x7c00 | nan;
+  }
+
+  let exponent = exponent - 127 + 15;
+  if exponent >= 0x1f {
+    return sign | 0x7c00;
+  }
+  if exponent <= 0 {
+    // subnormal, the implicit leading bit becomes explicit
+    if exponent < -10 {
+      return sign;
+    }
+    let mantissa = mantissa | 0x0080_0000;
+    let shift = (14 - exponent) as u32;
+    let round = (mantissa >> (shift - 1)) & 1;
+    return sign | ((mantissa >> shift) + round) as u16;
+  }
+
+  // a carry out of the mantissa correctly bumps the exponent
+  let round = (mantissa >> 12) & 1;
+  sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
+// Radiance RGBE picture with run-length encoded scanlines,
+// the camera is stored in the standard VIEW line
+pub fn write_hdr<W: Write>(mut out: W, frame: &Frame) -> io::Result<()> {
+  let (width, height) = (frame.radiance.width, frame.radiance.height);
+  let camera = frame.camera;
+
+  let vertical = camera.fov_degrees.to_radians();
+  let horizontal = 2.0 * ((vertical / 2.0).tan() * camera.aspect_ratio).atan();
+  let (p, d, u) = (camera.position, camera.direction, camera.basis.v);
+
+  writeln!(out, "#?RADIANCE")?;
+  writeln!(out, "SOFTWARE=raytreizer")?;
+  writeln!(out, "FORMAT=32-bit_rle_rgbe")?;
+  writeln!(
+    out,
+    "VIEW= -vtv -vp {} {} {} -vd {} {} {} -vu {} {} {} -vh {} -vv {}",
+    p.x, p.y, p.z, d.x, d.y, d.z, u.x, u.y, u.z,
+    horizontal.to_degrees(), vertical.to_degrees(),
+  )?;
+  writeln!(
+    out,
+    "LENS= -focus {} -aperture {} -blades {} -rotation {}",
+    camera.focus_distance, camera.aperture, camera.aperture_blades, camera.blade_rotation,
+  )?;
+  write!(out, "\n-Y {height} +X {width}\n")?;
+
+  // the run-length scheme only covers widths from 8 to 32767, others are written flat
+  let encode = (8..0x8000).contains(&width);
+  let mut planes: [Vec<u8>; 4] = std::array::from_fn(|_| Vec::with_capacity(width));
+  for row in frame.radiance.pixels.chunks(width) {
+    if !encode {
+      for &pixel in row {
+        out.write_all(&rgbe(pixel))?;
+      }
+      continue;
+    }
+
+    for plane in &mut planes {
+      plane.clear();
+    }
+    for &pixel in row {
+      for (plane, byte) in planes.iter_mut().zip(rgbe(pixel)) {
+        plane.push(byte);
+      }
+    }
+
+    out.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+    for plane in &planes {
+      write_rle(&mut out, plane)?;
+    }
+  }
+
+  Ok(())
+}
+
+// shared exponent encoding, negative and non finite values become black
+fn rgbe(pixel: [f32; 3]) -> [u8; 4] {
+  let [r, g, b] = pixel.map(|v| if v.is_finite() { v.max(0.0) } else { 0.0 });
+  let peak = r.max(g).max(b);
+  if peak < 1e-32 {
+    return [0; 4];
+  }
+
+  // peak = mantissa * 2^exponent with the mantissa in [0.5, 1)
+  let exponent = peak.log2().floor() as i32 + 1;
+  let scale = 256.0 / 2f32.powi(exponent);
+  let byte = |v: f32| (v * scale).min(255.0) as u8;
+  [byte(r), byte(g), byte(b), (exponent + 128).clamp(0, 255) as u8]
+}
+
+// one component of a scanline as runs of at least 4 equal bytes and literal dumps
+fn write_rle<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+  const MIN_RUN: usize = 4;
+  let mut i = 0;
+  while i < data.len() {
+    // look for the next run worth encoding
+    let mut run_start = i;
+    let mut run_length = 0;
+    while run_start < data.len() {
+      run_length = 1;
+      while run_start + run_length < data.len()
+        && run_length < 127
+        && data[run_start + run_length] == data[run_start] {
+        run_length += 1;
+      }
+      if run_length >= MIN_RUN {
+        break;
+      }
+      run_start += run_length;
+    }
+
+    while i < run_start {
+      let count = (run_start - i).min(128);
+      out.write_all(&[count as u8])?;
+      out.write_all(&data[i..i + count])?;
+      i += count;
+    }
+
+    if run_length >= MIN_RUN {
+      out.write_all(&[128 + run_length as u8, data[run_start]])?;
+      i = run_start + run_length;
+    }
+  }
+  Ok(())
+}
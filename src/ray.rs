@@ -1,6 +1,7 @@
 use crate::math::Vec3;
 use crate::math::Point3;
 
+#[derive(Clone, Copy)]
 pub struct Ray {
   pub o: Point3,
   pub dir: Vec3,
@@ -0,0 +1,386 @@
+// sample generators for everything random in a pixel: position, lens, lights and bsdfs
+//
+// each call to `get_1d` or `get_2d` is a new dimension, consumers should ask in the
+// same order for every sample so low discrepancy sequences line up across samples
+
+use crate::utils::{ mix, Rng };
+
+pub trait Sampler {
+  // moves to sample `index` of the pixel, dimensions start over from the first
+  fn start_sample(&mut self, index: usize);
+  // uniform in [0, 1)
+  fn get_1d(&mut self) -> f64;
+  // uniform in [0, 1)^2
+  fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum SamplerKind {
+  // every dimension drawn independently
+  Independent,
+  // correlated multi-jittered, strata over the samples of one render pass
+  Stratified,
+  // cranley-patterson rotated halton sequence, one prime base per dimension
+  Halton,
+  // shuffled owen scrambled sobol (0, 2) sequence for every pair of dimensions
+  #[default]
+  Sobol,
+}
+
+impl SamplerKind {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "independent" => Some(SamplerKind::Independent),
+      "stratified"  => Some(SamplerKind::Stratified),
+      "halton"      => Some(SamplerKind::Halton),
+      "sobol"       => Some(SamplerKind::Sobol),
+      _ => None,
+    }
+  }
+
+  // sampler for pixel (`x`, `y`) of a render seeded with `seed`,
+  // `samples` is how many samples each render pass takes per pixel
+  pub fn create(&self, seed: u64, x: usize, y: usize, samples: usize) -> Box<dyn Sampler> {
+    let pixel = mix(seed ^ mix(((y as u64) << 32) | x as u64));
+    match self {
+      SamplerKind::Independent => Box::new(IndependentSampler::new(seed, x, y)),
+      SamplerKind::Stratified  => Box::new(StratifiedSampler::new(pixel, samples)),
+      SamplerKind::Halton      => Box::new(HaltonSampler::new(pixel)),
+      SamplerKind::Sobol       => Box::new(SobolSampler::new(pixel)),
+    }
+  }
+}
+
+pub struct IndependentSampler {
+  seed: u64,
+  x   : usize,
+  y   : usize,
+  rng : Rng,
+}
+
+impl IndependentSampler {
+  pub fn new(seed: u64, x: usize, y: usize) -> Self {
+    IndependentSampler { seed, x, y, rng: Rng::for_sample(seed, x, y, 0) }
+  }
+}
+
+impl Sampler for IndependentSampler {
+  fn start_sample(&mut self, index: usize) {
+    self.rng = Rng::for_sample(self.seed, self.x, self.y, index);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    self.rng.double()
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.rng.double(), self.rng.double())
+  }
+}
+
+// kensler's correlated multi-jittered sampling, the samples of a pass fall in
+// distinct strata of every dimension and of every 2D projection,
+// later passes reshuffle the strata so progressive rendering keeps converging
+pub struct StratifiedSampler {
+  pixel    : u64,
+  samples  : usize,
+  pattern  : u32, // stratum shuffle of the current pass
+  index    : u32, // sample index inside the pass
+  dimension: u32,
+}
+
+impl StratifiedSampler {
+  pub fn new(pixel: u64, samples: usize) -> Self {
+    StratifiedSampler { pixel, samples: samples.max(1), pattern: 0, index: 0, dimension: 0 }
+  }
+
+  fn next_pattern(&mut self) -> u32 {
+    self.dimension += 1;
+    mix(self.pixel ^ ((self.pattern as u64) << 32) ^ self.dimension as u64) as u32
+  }
+}
+
+impl Sampler for StratifiedSampler {
+  fn start_sample(&mut self, index: usize) {
+    self.pattern   = (index / self.samples) as u32;
+    self.index     = (index % self.samples) as u32;
+    self.dimension = 0;
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let p = self.next_pattern();
+    let n = self.samples as u32;
+    let stratum = permute(self.index, n, p.wrapping_mul(0x51633e2d));
+    (stratum as f64 + jitter(self.index, p.wrapping_mul(0x967a889b))) / n as f64
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    let p = self.next_pattern();
+    let n = self.samples as u32;
+    let m = ((n as f64).sqrt() as u32).max(1);
+    let rows = n.div_ceil(m);
+
+    let s  = permute(self.index, n, p.wrapping_mul(0x51633e2d));
+    let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+    let sy = permute(s / m, rows, p.wrapping_mul(0x02e5be93));
+    let jx = jitter(s, p.wrapping_mul(0x967a889b));
+    let jy = jitter(s, p.wrapping_mul(0x368cc8b7));
+    (
+      (sx as f64 + (sy as f64 + jx) / rows as f64) / m as f64,
+      (s as f64 + jy) / n as f64,
+    )
+  }
+}
+
+// primes used as halton bases, dimensions past the table fall back to random numbers
+const PRIMES: [u32; 48] = [
+  2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+  59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+  137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+];
+
+pub struct HaltonSampler {
+  pixel    : u64,
+  index    : u64,
+  dimension: usize,
+  rng      : Rng,
+}
+
+impl HaltonSampler {
+  pub fn new(pixel: u64) -> Self {
+    HaltonSampler { pixel, index: 0, dimension: 0, rng: Rng::new(pixel, 0) }
+  }
+}
+
+impl Sampler for HaltonSampler {
+  fn start_sample(&mut self, index: usize) {
+    self.index     = index as u64;
+    self.dimension = 0;
+    self.rng       = Rng::new(self.pixel, index as u64);
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let Some(&base) = PRIMES.get(self.dimension) else {
+      return self.rng.double();
+    };
+    // the per-pixel rotation decorrelates neighbouring pixels walking the same sequence
+    let rotation = (mix(self.pixel ^ self.dimension as u64) >> 11) as f64 / (1u64 << 53) as f64;
+    self.dimension += 1;
+    (radical_inverse(self.index, base) + rotation).fract()
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    (self.get_1d(), self.get_1d())
+  }
+}
+
+// burley's practical hash-based owen scrambling: every dimension pair gets the
+// first two sobol dimensions with its own index shuffle and scrambles
+pub struct SobolSampler {
+  pixel    : u64,
+  index    : u32,
+  dimension: u64,
+}
+
+impl SobolSampler {
+  pub fn new(pixel: u64) -> Self {
+    SobolSampler { pixel, index: 0, dimension: 0 }
+  }
+
+  // the index shuffle and one scramble seed per axis for the next dimension
+  fn next_seeds(&mut self) -> [u32; 3] {
+    let hash = mix(self.pixel ^ mix(self.dimension));
+    self.dimension += 1;
+    [hash as u32, (hash >> 32) as u32, mix(hash) as u32]
+  }
+}
+
+impl Sampler for SobolSampler {
+  fn start_sample(&mut self, index: usize) {
+    self.index     = index as u32;
+    self.dimension = 0;
+  }
+
+  fn get_1d(&mut self) -> f64 {
+    let [shuffle, seed, _] = self.next_seeds();
+    let index = owen_scramble(self.index, shuffle);
+    unit(owen_scramble(index.reverse_bits(), seed))
+  }
+
+  fn get_2d(&mut self) -> (f64, f64) {
+    let [shuffle, seed_x, seed_y] = self.next_seeds();
+    let index = owen_scramble(self.index, shuffle);
+    (
+      unit(owen_scramble(index.reverse_bits(), seed_x)),
+      unit(owen_scramble(sobol_second(index), seed_y)),
+    )
+  }
+}
+
+// second sobol dimension, its direction numbers follow v_k = v_{k-1} ^ (v_{k-1} >> 1)
+fn sobol_second(mut index: u32) -> u32 {
+  let mut result = 0;
+  let mut direction = 1u32 << 31;
+  while index != 0 {
+    if index & 1 != 0 {
+      result ^= direction;
+    }
+    index >>= 1;
+    direction ^= direction >> 1;
+  }
+  result
+}
+
+// nested uniform scramble of the bits of `x`, a hash of the reversed bits so each
+// bit only depends on the ones above it
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+  let mut x = x.reverse_bits();
+  x = x.wrapping_add(seed);
+  x ^= x.wrapping_mul(0x6c50b47c);
+  x ^= x.wrapping_mul(0xb82f1e52);
+  x ^= x.wrapping_mul(0xc7afe638);
+  x ^= x.wrapping_mul(0x8d22f6e6);
+  x.reverse_bits()
+}
+
+fn radical_inverse(mut index: u64, base: u32) -> f64 {
+  let base = base as u64;
+  let inverse = 1.0 / base as f64;
+  let mut digits = 0u64;
+  let mut scale = 1.0;
+  while index > 0 {
+    digits = digits * base + index % base;
+    scale *= inverse;
+    index /= base;
+  }
+  (digits as f64 * scale).min(1.0 - f64::EPSILON)
+}
+
+// kensler's hashed permutation of `i` among `length` elements
+fn permute(mut i: u32, length: u32, p: u32) -> u32 {
+  if length <= 1 {
+    return 0;
+  }
+  let mut w = length - 1;
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+  loop {
+    i ^= p;
+    i = i.wrapping_mul(0xe170893d);
+    i ^= p >> 16;
+    i ^= (i & w) >> 4;
+    i ^= p >> 8;
+    i = i.wrapping_mul(0x0929eb3f);
+    i ^= p >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | p >> 27);
+    i = i.wrapping_mul(0x6935fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dcb303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e501cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860a3df);
+    i &= w;
+    i ^= i >> 5;
+    if i < length {
+      break;
+    }
+  }
+  i.wrapping_add(p) % length
+}
+
+// kensler's hashed offset in [0, 1) for the jitter inside a stratum
+fn jitter(mut i: u32, p: u32) -> f64 {
+  i ^= p;
+  i ^= i >> 17;
+  i ^= i >> 10;
+  i = i.wrapping_mul(0xb36534e5);
+  i ^= i >> 12;
+  i ^= i >> 21;
+  i = i.wrapping_mul(0x93fc4795);
+  i ^= 0xdf6e307f;
+  i ^= i >> 17;
+  i = i.wrapping_mul(1 | p >> 18);
+  unit(i)
+}
+
+fn unit(bits: u32) -> f64 {
+  bits as f64 / (1u64 << 32) as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+  // 1d and 2d values of the first samples of a pixel, over more dimensions than
+  // the halton table covers
+  fn values(kind: SamplerKind, seed: u64, x: usize, y: usize, samples: usize) -> Vec<f64> {
+    let mut sampler = kind.create(seed, x, y, samples);
+    let mut values = Vec::new();
+    for sample in 0..2 * samples {
+      sampler.start_sample(sample);
+      for _ in 0..30 {
+        values.push(sampler.get_1d());
+        let (u, v) = sampler.get_2d();
+        values.extend([u, v]);
+      }
+    }
+    values
+  }
+
+  #[test]
+  fn samples_stay_in_unit_interval() {
+    for kind in KINDS {
+      for (x, y) in [(0, 0), (7, 3), (1279, 719)] {
+        for value in values(kind, 9, x, y, 16) {
+          assert!((0.0..1.0).contains(&value), "{kind:?} gave {value}");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn fixed_seed_repeats() {
+    for kind in KINDS {
+      assert_eq!(values(kind, 9, 4, 5, 8), values(kind, 9, 4, 5, 8), "{kind:?}");
+      assert_ne!(values(kind, 9, 4, 5, 8), values(kind, 10, 4, 5, 8), "{kind:?}");
+      assert_ne!(values(kind, 9, 4, 5, 8), values(kind, 9, 5, 4, 8), "{kind:?}");
+    }
+
+    // samples do not depend on the ones drawn before them
+    for kind in KINDS {
+      let mut sampler = kind.create(9, 4, 5, 8);
+      sampler.start_sample(3);
+      let first = (sampler.get_1d(), sampler.get_2d());
+      sampler.start_sample(6);
+      sampler.get_2d();
+      sampler.start_sample(3);
+      assert_eq!((sampler.get_1d(), sampler.get_2d()), first, "{kind:?}");
+    }
+  }
+
+  #[test]
+  fn passes_are_stratified() {
+    // every dimension of a pass of n samples puts one sample in each 1/n interval
+    for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+      let n = 16;
+      let mut sampler = kind.create(9, 2, 3, n);
+      let mut strata = vec![[false; 3]; n];
+      for sample in 0..n {
+        sampler.start_sample(sample);
+        let (u, v) = sampler.get_2d();
+        for (axis, value) in [sampler.get_1d(), u, v].into_iter().enumerate() {
+          let stratum = (value * n as f64) as usize;
+          assert!(!strata[stratum][axis], "{kind:?} reused a stratum");
+          strata[stratum][axis] = true;
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,468 @@
+use std::cell::Cell;
+use std::fmt;
+
+use crate::math::{ Point3, Transform, Vec3 };
+use crate::ray::Ray;
+use crate::scene::object::{ HitRecord, Hittable };
+
+// number of centroid bins evaluated per axis when looking for a split
+const SAH_BINS: usize = 12;
+// relative cost of traversing a node compared to intersecting a primitive
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+const MAX_LEAF_SIZE: usize = 4;
+// traversal stack size, deeper trees than this are not produced by the SAH build
+const STACK_SIZE: usize = 64;
+
+// axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+  pub min: Point3,
+  pub max: Point3,
+}
+
+impl Aabb {
+  pub fn new(a: Point3, b: Point3) -> Self {
+    Aabb {
+      min: Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+      max: Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+    }
+  }
+
+  // box containing nothing, neutral element for `union`
+  pub fn empty() -> Self {
+    Aabb {
+      min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+      max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    }
+  }
+
+  pub fn union(&self, other: &Aabb) -> Aabb {
+    Aabb {
+      min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+      max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+    }
+  }
+
+  pub fn grow(&self, point: Point3) -> Aabb {
+    self.union(&Aabb { min: point, max: point })
+  }
+
+  // box around the transformed corners of this one
+  pub fn transformed(&self, transform: &Transform) -> Aabb {
+    let mut bounds = Aabb::empty();
+    for corner in 0..8 {
+      let pick = |axis: usize| if corner & (1 << axis) == 0 { self.min[axis] } else { self.max[axis] };
+      bounds = bounds.grow(transform.point(Point3::new(pick(0), pick(1), pick(2))));
+    }
+    bounds
+  }
+
+  pub fn centroid(&self) -> Point3 {
+    (self.min + self.max) * 0.5
+  }
+
+  pub fn surface_area(&self) -> f64 {
+    let d = self.max - self.min;
+    if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+      return 0.0;
+    }
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+  }
+
+  // slab test against the ray segment [0, t_max], `inv_dir` is 1 / ray.dir per component
+  pub fn hit(&self, ray: &Ray, inv_dir: &Vec3, t_max: f64) -> bool {
+    let mut t0 = 0.0;
+    let mut t1 = t_max;
+
+    for axis in 0..3 {
+      let mut near = (self.min[axis] - ray.o[axis]) * inv_dir[axis];
+      let mut far  = (self.max[axis] - ray.o[axis]) * inv_dir[axis];
+      if near > far {
+        std::mem::swap(&mut near, &mut far);
+      }
+
+      // NaN from 0 * inf (ray on a slab boundary) keeps the previous bounds
+      t0 = if near > t0 { near } else { t0 };
+      t1 = if far  < t1 { far  } else { t1 };
+      if t0 > t1 {
+        return false;
+      }
+    }
+
+    true
+  }
+}
+
+// rays cast and tests they took, casts into the tree of a mesh add their tests to the
+// ray that reached the mesh instead of counting as rays of their own
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TraversalCounts {
+  pub rays           : u64,
+  pub box_tests      : u64,
+  pub primitive_tests: u64,
+}
+
+impl TraversalCounts {
+  pub fn add(&mut self, other: TraversalCounts) {
+    self.rays            += other.rays;
+    self.box_tests       += other.box_tests;
+    self.primitive_tests += other.primitive_tests;
+  }
+}
+
+// every thread counts its own casts so rendering threads never share a cache line,
+// renderers collect them with `take_counts`
+thread_local! {
+  static COUNTS : Cell<TraversalCounts> = Cell::new(TraversalCounts::default());
+  // casts in progress on this thread, more than one inside meshes
+  static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+// the counts of this thread since they were last taken
+pub fn take_counts() -> TraversalCounts {
+  COUNTS.take()
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BvhStats {
+  pub nodes          : usize,
+  pub leaves         : usize,
+  pub depth          : usize,
+  pub unbounded      : usize, // objects such as planes kept outside the tree
+  pub rays           : u64,
+  pub box_tests      : u64,
+  pub primitive_tests: u64,
+}
+
+impl fmt::Display for BvhStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} nodes, {} leaves, depth {}, {} unbounded | last frame: {} rays, {} box tests, {} primitive tests",
+      self.nodes, self.leaves, self.depth, self.unbounded,
+      self.rays, self.box_tests, self.primitive_tests,
+    )
+  }
+}
+
+enum NodeKind {
+  // children are stored at `index + 1` and `right`
+  Interior { right: usize },
+  // range into `Bvh::indices`
+  Leaf { start: usize, count: usize },
+}
+
+struct Node {
+  bounds: Aabb,
+  kind  : NodeKind,
+}
+
+// bounding volume hierarchy over the bounded items of a slice, such as the objects
+// of a scene or the triangles of a mesh, built with a binned surface area heuristic
+// and stored depth-first in a flat array
+pub struct Bvh {
+  nodes    : Vec<Node>,
+  indices  : Vec<usize>, // object indices referenced by the leaves
+  unbounded: Vec<usize>, // objects without a bounding box, tested on every ray
+  depth    : usize,
+}
+
+// object index together with its cached bounds and centroid
+struct BuildItem {
+  index   : usize,
+  bounds  : Aabb,
+  centroid: Point3,
+}
+
+impl Bvh {
+  pub fn build<T: Hittable>(objects: &[T]) -> Self {
+    let mut items     = Vec::new();
+    let mut unbounded = Vec::new();
+
+    for (index, object) in objects.iter().enumerate() {
+      match object.bounding_box() {
+        Some(bounds) => items.push(BuildItem { index, bounds, centroid: bounds.centroid() }),
+        None => unbounded.push(index),
+      }
+    }
+
+    let mut bvh = Bvh {
+      nodes: Vec::with_capacity(2 * items.len()),
+      indices: Vec::with_capacity(items.len()),
+      unbounded,
+      depth: 0,
+    };
+
+    if !items.is_empty() {
+      bvh.build_node(&mut items, 1);
+    }
+    bvh
+  }
+
+  // appends the subtree for `items` and returns the index of its root
+  fn build_node(&mut self, items: &mut [BuildItem], depth: usize) -> usize {
+    self.depth = self.depth.max(depth);
+
+    let bounds = items.iter().fold(Aabb::empty(), |b, item| b.union(&item.bounds));
+    let node_index = self.nodes.len();
+    self.nodes.push(Node { bounds, kind: NodeKind::Leaf { start: 0, count: 0 } });
+
+    // past the traversal stack size everything left goes into one leaf
+    let split = if items.len() > 1 && depth < STACK_SIZE - 1 {
+      Self::find_split(items, &bounds)
+    } else {
+      None
+    };
+
+    let Some(mid) = split else {
+      let start = self.indices.len();
+      self.indices.extend(items.iter().map(|item| item.index));
+      self.nodes[node_index].kind = NodeKind::Leaf { start, count: items.len() };
+      return node_index;
+    };
+
+    let (left, right) = items.split_at_mut(mid);
+    self.build_node(left, depth + 1);
+    let right = self.build_node(right, depth + 1);
+    self.nodes[node_index].kind = NodeKind::Interior { right };
+
+    node_index
+  }
+
+  // partitions `items` along the cheapest SAH split and returns the split position,
+  // None when keeping them all in one leaf is cheaper
+  fn find_split(items: &mut [BuildItem], bounds: &Aabb) -> Option<usize> {
+    let centroid_bounds = items.iter().fold(Aabb::empty(), |b, item| b.grow(item.centroid));
+    let leaf_cost = INTERSECTION_COST * items.len() as f64;
+
+    let mut best: Option<(usize, usize, f64)> = None; // axis, bin, cost
+    for axis in 0..3 {
+      let lo = centroid_bounds.min[axis];
+      let extent = centroid_bounds.max[axis] - lo;
+      if extent <= 0.0 {
+        continue;
+      }
+
+      let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+      for item in items.iter() {
+        let b = Self::bin_of(item.centroid[axis], lo, extent);
+        bins[b].0 = bins[b].0.union(&item.bounds);
+        bins[b].1 += 1;
+      }
+
+      // sweep from the right to get the cost of every split plane in one pass
+      let mut right_area  = [0.0; SAH_BINS];
+      let mut right_count = [0usize; SAH_BINS];
+      let mut acc = (Aabb::empty(), 0usize);
+      for b in (1..SAH_BINS).rev() {
+        acc = (acc.0.union(&bins[b].0), acc.1 + bins[b].1);
+        right_area[b]  = acc.0.surface_area();
+        right_count[b] = acc.1;
+      }
+
+      let mut left = (Aabb::empty(), 0usize);
+      for b in 1..SAH_BINS {
+        left = (left.0.union(&bins[b - 1].0), left.1 + bins[b - 1].1);
+        if left.1 == 0 || right_count[b] == 0 {
+          continue;
+        }
+
+        let cost = TRAVERSAL_COST
+                 + INTERSECTION_COST
+                 * (left.0.surface_area() * left.1 as f64 + right_area[b] * right_count[b] as f64)
+                 / bounds.surface_area().max(f64::MIN_POSITIVE);
+        if best.is_none_or(|(_, _, c)| cost < c) {
+          best = Some((axis, b, cost));
+        }
+      }
+    }
+
+    let (axis, bin, cost) = best?;
+    if cost >= leaf_cost && items.len() <= MAX_LEAF_SIZE {
+      return None;
+    }
+
+    let lo = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lo;
+    let mut mid = 0;
+    for i in 0..items.len() {
+      if Self::bin_of(items[i].centroid[axis], lo, extent) < bin {
+        items.swap(i, mid);
+        mid += 1;
+      }
+    }
+
+    Some(mid)
+  }
+
+  fn bin_of(value: f64, lo: f64, extent: f64) -> usize {
+    (((value - lo) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+  }
+
+  // closest hit and the index of the object it belongs to,
+  // `objects` must be the slice the tree was built from
+  pub fn cast<'a, T: Hittable>(&self, objects: &'a [T], ray: &Ray) -> Option<(usize, HitRecord<'a>)> {
+    let mut closest: Option<(usize, HitRecord)> = None;
+    let mut closest_t = f64::INFINITY;
+    let mut box_tests = 0;
+    let mut primitive_tests = 0;
+    let nested = NESTING.replace(NESTING.get() + 1) > 0;
+
+    for &index in &self.unbounded {
+      primitive_tests += 1;
+      if let Some(hit) = objects[index].hit(ray)
+        && hit.t < closest_t {
+        closest_t = hit.t;
+        closest = Some((index, hit));
+      }
+    }
+
+    if !self.nodes.is_empty() {
+      let inv_dir = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+      let mut stack = [0usize; STACK_SIZE];
+      let mut len = 1;
+
+      while len > 0 {
+        len -= 1;
+        let node_index = stack[len];
+        let node = &self.nodes[node_index];
+        box_tests += 1;
+        if !node.bounds.hit(ray, &inv_dir, closest_t) {
+          continue;
+        }
+
+        match node.kind {
+          NodeKind::Leaf { start, count } => {
+            for &index in &self.indices[start..start + count] {
+              primitive_tests += 1;
+              if let Some(hit) = objects[index].hit(ray)
+                && hit.t < closest_t {
+                closest_t = hit.t;
+                closest = Some((index, hit));
+              }
+            }
+          }
+          NodeKind::Interior { right } => {
+            stack[len]     = right;
+            stack[len + 1] = node_index + 1;
+            len += 2;
+          }
+        }
+      }
+    }
+
+    NESTING.set(NESTING.get() - 1);
+    let mut counts = COUNTS.get();
+    counts.add(TraversalCounts { rays: u64::from(!nested), box_tests, primitive_tests });
+    COUNTS.set(counts);
+
+    closest
+  }
+
+  // shape of the tree, `counts` are the traversals to report with it
+  pub fn stats(&self, counts: TraversalCounts) -> BvhStats {
+    BvhStats {
+      nodes: self.nodes.len(),
+      leaves: self.nodes.iter().filter(|n| matches!(n.kind, NodeKind::Leaf { .. })).count(),
+      depth: self.depth,
+      unbounded: self.unbounded.len(),
+      rays: counts.rays,
+      box_tests: counts.box_tests,
+      primitive_tests: counts.primitive_tests,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+  use crate::color::Color;
+  use crate::scene::material::{ Material, Solid };
+  use crate::scene::object::{ Mesh, Object, Plane, Sphere, Triangle };
+  use crate::scene::texture::constant;
+  use crate::utils::Rng;
+
+  fn random_point(rng: &mut Rng, extent: f64) -> Point3 {
+    Point3::new(rng.double() - 0.5, rng.double() - 0.5, rng.double() - 0.5) * (2.0 * extent)
+  }
+
+  // spheres and triangles of every size scattered around the origin, and a plane
+  // kept outside the tree
+  fn random_scene(rng: &mut Rng) -> Vec<Object> {
+    let material: Arc<dyn Material + Send + Sync> = Arc::new(Solid { albedo: constant(Color::rgb(0.5, 0.5, 0.5)) });
+    let mut objects = vec![Object::Plane(Plane {
+      anchor  : Point3::new(0.0, -12.0, 0.0),
+      normal  : Vec3::new(0.0, 1.0, 0.0),
+      material: Arc::clone(&material),
+    })];
+    for i in 0..300 {
+      let center = random_point(rng, 10.0);
+      let size = 0.05 + 2.0 * rng.double() * rng.double();
+      objects.push(if i % 2 == 0 {
+        Object::Sphere(Sphere { center, radius: size, material: Arc::clone(&material) })
+      } else {
+        Object::Triangle(Triangle {
+          vertices: [center, center + random_point(rng, size), center + random_point(rng, size)],
+          normals : None,
+          uvs     : None,
+          material: Arc::clone(&material),
+        })
+      });
+    }
+    objects
+  }
+
+  #[test]
+  fn cast_matches_linear_scan() {
+    let mut rng = Rng::new(7, 1);
+    let objects = random_scene(&mut rng);
+    let bvh = Bvh::build(&objects);
+    assert!(bvh.stats(TraversalCounts::default()).depth < STACK_SIZE);
+
+    let mut hits = 0;
+    for _ in 0..5000 {
+      let origin = random_point(&mut rng, 15.0);
+      let dir = (random_point(&mut rng, 1.0) - origin * 0.05).unit();
+      let ray = Ray::new(origin, dir);
+
+      let expected = objects
+        .iter()
+        .enumerate()
+        .filter_map(|(index, object)| object.hit(&ray).map(|hit| (index, hit.t)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+      let found = bvh.cast(&objects, &ray).map(|(index, hit)| (index, hit.t));
+      assert_eq!(found, expected);
+      hits += usize::from(found.is_some());
+    }
+    // most rays should find something for the test to mean anything
+    assert!(hits > 2500, "{hits} hits");
+  }
+
+  #[test]
+  fn counts_mesh_casts_with_the_scene_ray() {
+    let mut rng = Rng::new(3, 1);
+    let triangles = random_scene(&mut rng)
+      .into_iter()
+      .filter_map(|object| match object {
+        Object::Triangle(triangle) => Some(triangle),
+        _ => None,
+      })
+      .collect();
+    let objects = vec![Object::Mesh(Mesh::new(triangles))];
+    let bvh = Bvh::build(&objects);
+
+    take_counts();
+    let ray = Ray::new(Point3::new(0.0, 0.0, -30.0), Vec3::new(0.0, 0.0, 1.0));
+    for _ in 0..10 {
+      bvh.cast(&objects, &ray);
+    }
+    let counts = take_counts();
+    assert_eq!(counts.rays, 10);
+    // the mesh tree adds its own tests to the single box and primitive of the scene tree
+    assert!(counts.box_tests > 10 && counts.primitive_tests > 10, "{counts:?}");
+    assert_eq!(take_counts().rays, 0);
+  }
+}
@@ -0,0 +1,200 @@
+// light arriving from infinitely far away, seen by every ray that leaves the scene
+//
+// environment maps are equirectangular images: the left and right edges meet behind
+// the camera's default view along -z, which sits in the middle of the image, and the
+// top row looks straight up. pixels are looked up without filtering so the radiance
+// follows the same steps as the distribution used to sample it
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::input::read_radiance;
+use crate::math::Vec3;
+use crate::scene::texture::TextureError;
+use crate::tonemap::luminance;
+
+pub enum Environment {
+  Color(Vec3),
+  // blends from `bottom` straight down to `top` straight up
+  Gradient { bottom: Vec3, top: Vec3 },
+  Map(EnvironmentMap),
+}
+
+impl Default for Environment {
+  // white to sky blue
+  fn default() -> Self {
+    Environment::Gradient { bottom: Vec3::new(1.0, 1.0, 1.0), top: Vec3::new(0.3, 0.5, 1.0) }
+  }
+}
+
+impl Environment {
+  // radiance arriving along `-dir`, from far away in direction `dir`
+  pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+    match self {
+      Environment::Color(color) => *color,
+      Environment::Gradient { bottom, top } => {
+        let a = 0.5 * (dir.unit().y + 1.0);
+        (1.0 - a) * *bottom + a * *top
+      }
+      Environment::Map(map) => map.radiance(dir),
+    }
+  }
+
+  // direction picked with a density following the map's brightness, its radiance and
+  // the density per solid angle, None for environments that are not importance sampled
+  pub fn sample(&self, uv: (f64, f64)) -> Option<(Vec3, Vec3, f64)> {
+    match self {
+      Environment::Map(map) => map.sample(uv),
+      _ => None,
+    }
+  }
+
+  // density per solid angle of `sample` picking `dir`
+  pub fn pdf(&self, dir: &Vec3) -> f64 {
+    match self {
+      Environment::Map(map) => map.pdf(dir),
+      _ => 0.0,
+    }
+  }
+}
+
+// HDR image wrapped around the scene, pixels are sampled in proportion to their
+// luminance times the solid angle they cover
+pub struct EnvironmentMap {
+  width    : usize,
+  height   : usize,
+  pixels   : Vec<Vec3>,
+  intensity: f64,
+  // turn of the map around +y, counter-clockwise seen from above
+  rotation : (f64, f64), // sine, cosine
+
+  // probability of every pixel, cumulative probabilities of the rows and, within each
+  // row, of its pixels
+  weights  : Vec<f64>,
+  rows     : Vec<f64>,
+  columns  : Vec<f64>,
+}
+
+impl EnvironmentMap {
+  // reads an `.hdr` or `.exr` file, `rotation` is in degrees
+  pub fn load(path: &Path, rotation: f64, intensity: f64) -> Result<Self, TextureError> {
+    let image = read_radiance(path).map_err(|message| TextureError { file: path.to_path_buf(), message })?;
+    let pixels = image.pixels.iter().map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+    Ok(EnvironmentMap::new(image.width, image.height, pixels, rotation, intensity))
+  }
+
+  // `pixels` holds linear radiance, rows top to bottom
+  pub fn new(width: usize, height: usize, pixels: Vec<Vec3>, rotation: f64, intensity: f64) -> Self {
+    // rows near the poles are squeezed onto a small solid angle
+    let mut weights: Vec<f64> = pixels
+      .iter()
+      .enumerate()
+      .map(|(i, &pixel)| {
+        let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+        luminance(pixel).max(0.0) * theta.sin()
+      })
+      .collect();
+    let total: f64 = weights.iter().sum();
+    if total > 0.0 {
+      weights.iter_mut().for_each(|w| *w /= total);
+    }
+
+    let mut rows    = Vec::with_capacity(height);
+    let mut columns = Vec::with_capacity(width * height);
+    let mut sum = 0.0;
+    for row in weights.chunks(width) {
+      let row_sum: f64 = row.iter().sum();
+      sum += row_sum;
+      rows.push(sum);
+
+      let mut partial = 0.0;
+      for (x, weight) in row.iter().enumerate() {
+        partial += weight;
+        // rows that are never picked still get a valid distribution
+        columns.push(if row_sum > 0.0 { partial / row_sum } else { (x + 1) as f64 / width as f64 });
+      }
+    }
+
+    EnvironmentMap {
+      width,
+      height,
+      pixels,
+      intensity,
+      rotation: rotation.to_radians().sin_cos(),
+      weights,
+      rows,
+      columns,
+    }
+  }
+
+  // world direction to image coordinates in [0, 1], u to the right and v downwards
+  fn uv(&self, dir: &Vec3) -> (f64, f64) {
+    let d = dir.unit();
+    // turn the direction back by the map's rotation
+    let (sin, cos) = self.rotation;
+    let (x, z) = (d.x * cos - d.z * sin, d.x * sin + d.z * cos);
+    let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+    let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+    (u.rem_euclid(1.0), v)
+  }
+
+  fn direction(&self, (u, v): (f64, f64)) -> Vec3 {
+    let (sin_theta, cos_theta) = (v * PI).sin_cos();
+    let (sin_phi, cos_phi) = ((u - 0.5) * 2.0 * PI).sin_cos();
+    let (x, z) = (sin_theta * sin_phi, -sin_theta * cos_phi);
+    let (sin, cos) = self.rotation;
+    Vec3::new(x * cos + z * sin, cos_theta, -x * sin + z * cos)
+  }
+
+  fn pixel_index(&self, (u, v): (f64, f64)) -> usize {
+    let x = ((u * self.width as f64) as usize).min(self.width - 1);
+    let y = ((v * self.height as f64) as usize).min(self.height - 1);
+    y * self.width + x
+  }
+
+  fn radiance(&self, dir: &Vec3) -> Vec3 {
+    self.pixels[self.pixel_index(self.uv(dir))] * self.intensity
+  }
+
+  fn sample(&self, (s, t): (f64, f64)) -> Option<(Vec3, Vec3, f64)> {
+    if self.rows.last().is_none_or(|&total| total <= 0.0) {
+      return None;
+    }
+
+    let (y, v) = pick(&self.rows, s);
+    let (x, u) = pick(&self.columns[y * self.width..(y + 1) * self.width], t);
+    let uv = ((x as f64 + u) / self.width as f64, (y as f64 + v) / self.height as f64);
+
+    let dir = self.direction(uv);
+    let pdf = self.pdf_uv(y * self.width + x, uv.1);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some((dir, self.pixels[y * self.width + x] * self.intensity, pdf))
+  }
+
+  fn pdf(&self, dir: &Vec3) -> f64 {
+    let uv = self.uv(dir);
+    self.pdf_uv(self.pixel_index(uv), uv.1)
+  }
+
+  // pixel probability spread over its area in the image, then over the solid angle
+  // that area covers
+  fn pdf_uv(&self, index: usize, v: f64) -> f64 {
+    let sin_theta = (v * PI).sin();
+    if sin_theta <= 0.0 {
+      return 0.0;
+    }
+    self.weights[index] * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+  }
+}
+
+// bucket of a cumulative distribution holding `s` and where `s` falls within it
+fn pick(cdf: &[f64], s: f64) -> (usize, f64) {
+  let s = s * cdf[cdf.len() - 1];
+  let i = cdf.partition_point(|&c| c <= s).min(cdf.len() - 1);
+  let low = if i == 0 { 0.0 } else { cdf[i - 1] };
+  let width = cdf[i] - low;
+  let offset = if width > 0.0 { ((s - low) / width).clamp(0.0, 1.0 - f64::EPSILON) } else { 0.5 };
+  (i, offset)
+}
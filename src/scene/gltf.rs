@@ -0,0 +1,820 @@
+// loader for glTF 2.0 scenes: `.gltf` files with external or embedded (data uri)
+// buffers and images, and binary `.glb` files
+//
+// - nodes place their mesh in the world as an instance, nodes using the same mesh share
+//   its triangles. meshes with emissive materials are copied into world space instead,
+//   so they can be sampled as area lights
+// - triangle, strip and fan primitives are read with their normals and first set of
+//   texture coordinates, points and lines are skipped
+// - materials become MetallicRoughness with their base color and metallic-roughness
+//   textures, transmissive ones (KHR_materials_transmission, KHR_materials_ior) become
+//   Dielectric and emissive ones (KHR_materials_emissive_strength) DiffuseLight
+// - the first perspective camera met walking the scene becomes the camera, files without
+//   one are framed from +z
+// - KHR_lights_punctual lights become point, directional and spot lights, their candela
+//   and lux are turned into radiometric units at 683 lm/W
+//
+// animations, skins, morph targets, normal and occlusion maps, alpha modes, texture
+// transforms and other texture coordinate sets are ignored, files requiring any other
+// extension (such as draco compression) are refused
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::math::{ Matrix, Point3, Transform, Vec3 };
+use crate::scene::{ AmbientLight, Scene };
+use crate::scene::json::Json;
+use crate::scene::light::{ DirectionalLight, Light, PointLight, SpotLight };
+use crate::scene::material::{ Dielectric, DiffuseLight, Material, MetallicRoughness };
+use crate::scene::object::{ Hittable, Instance, Mesh, Object, Triangle };
+use crate::scene::texture::{ constant, Encoding, ImageTexture, SharedTexture, Tint, WrapMode };
+
+type SharedMaterial = Arc<dyn Material + Send + Sync>;
+
+// extensions whose data is understood, files requiring any other one are refused
+const SUPPORTED_EXTENSIONS: [&str; 4] = [
+  "KHR_lights_punctual",
+  "KHR_materials_emissive_strength",
+  "KHR_materials_ior",
+  "KHR_materials_transmission",
+];
+// luminous efficacy turning candela and lux into watts per steradian and square meter
+const LUMENS_PER_WATT: f64 = 683.0;
+// vertical field of view in degrees of the camera made up for files without one
+const DEFAULT_FOV: f64 = 45.0;
+// deepest node hierarchy walked, node cycles would otherwise never end
+const MAX_NODE_DEPTH: usize = 256;
+
+// primitive modes
+const TRIANGLES     : usize = 4;
+const TRIANGLE_STRIP: usize = 5;
+const TRIANGLE_FAN  : usize = 6;
+
+// like ParseError, printed after the name of the file that failed to load
+#[derive(Debug)]
+pub struct GltfError {
+  pub message: String,
+}
+
+impl fmt::Display for GltfError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for GltfError {}
+
+// loads the default scene of `path`, the camera renders `image_width` pixels wide
+pub fn load_gltf(path: &Path, image_width: usize, aspect_ratio: f64) -> Result<Scene, GltfError> {
+  let error = |message: String| GltfError { message };
+  let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+  let document = Document::read(&bytes, path.parent().unwrap_or(Path::new("."))).map_err(error)?;
+  document.scene(image_width, aspect_ratio).map_err(error)
+}
+
+struct Document<'a> {
+  json    : Json,
+  base_dir: &'a Path,
+  buffers : Vec<Vec<u8>>,
+}
+
+// a mesh's triangles in its own space
+struct LoadedMesh {
+  mesh    : Arc<Mesh>,
+  emissive: bool,
+}
+
+// what walking the node hierarchy collects
+struct Walk {
+  meshes : HashMap<usize, Option<LoadedMesh>>,
+  objects: Vec<Object>,
+  lights : Vec<Light>,
+  camera : Option<Camera>,
+}
+
+impl<'a> Document<'a> {
+  fn read(bytes: &[u8], base_dir: &'a Path) -> Result<Self, String> {
+    let (text, mut binary) = if bytes.starts_with(b"glTF") {
+      split_glb(bytes)?
+    } else {
+      (std::str::from_utf8(bytes).map_err(|_| "file is neither glTF JSON nor glb")?, None)
+    };
+    let json = Json::parse(text)?;
+
+    let version = json.get("asset").and_then(|a| a.get("version")).and_then(Json::as_str);
+    if !version.is_some_and(|v| v.starts_with("2.")) {
+      return Err("only glTF 2.0 files are supported".to_string());
+    }
+    for required in array(json.get("extensionsRequired")) {
+      let name = required.as_str().unwrap_or("");
+      if !SUPPORTED_EXTENSIONS.contains(&name) {
+        return Err(format!("required extension {name} is not supported"));
+      }
+    }
+
+    let mut buffers = Vec::new();
+    for (index, buffer) in array(json.get("buffers")).iter().enumerate() {
+      let length = buffer.get("byteLength").and_then(Json::as_usize).ok_or_else(|| format!("buffer {index} has no length"))?;
+      // a glb file's binary chunk is the first buffer, the one without a uri
+      let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) => read_uri(base_dir, uri)?,
+        None if index == 0 => binary.take().ok_or("buffer 0 has no uri and the file no binary chunk")?,
+        None => return Err(format!("buffer {index} has no uri")),
+      };
+      if data.len() < length {
+        return Err(format!("buffer {index} holds {} bytes, {length} expected", data.len()));
+      }
+      buffers.push(data);
+    }
+
+    Ok(Document { json, base_dir, buffers })
+  }
+
+  fn scene(&self, image_width: usize, aspect_ratio: f64) -> Result<Scene, String> {
+    let mut textures = HashMap::new();
+    let materials = (0..array(self.json.get("materials")).len())
+      .map(|index| self.material(index, &mut textures))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let mut walk = Walk { meshes: HashMap::new(), objects: Vec::new(), lights: Vec::new(), camera: None };
+    for root in self.roots()? {
+      self.visit(root, &Transform::identity(), 0, &materials, &mut walk, (image_width, aspect_ratio))?;
+    }
+
+    let camera = walk.camera.unwrap_or_else(|| {
+      let bounds = walk.objects.iter().filter_map(|o| o.bounding_box()).reduce(|a, b| a.union(&b));
+      let (center, radius) = bounds.map_or((Point3::zero(), 1.0), |b| ((b.min + b.max) * 0.5, (b.max - b.min).length() * 0.5));
+      // far enough back for the bounding sphere to fit the field of view
+      let distance = radius.max(1e-3) / (DEFAULT_FOV.to_radians() / 2.0).sin();
+      let position = center + Vec3::new(0.0, 0.0, distance);
+      Camera::new(position, Vec3::new(0.0, 0.0, -1.0), DEFAULT_FOV, aspect_ratio, image_width)
+    });
+
+    let mut scene = Scene::new(camera, AmbientLight { ratio: 0.0, color: Color::Rgb(Vec3::zero()) });
+    for object in walk.objects {
+      scene.add_object(object);
+    }
+    for light in walk.lights {
+      scene.add_light(light);
+    }
+    Ok(scene)
+  }
+
+  // element `index` of the top level array `name`
+  fn item(&self, name: &str, index: usize) -> Result<&Json, String> {
+    array(self.json.get(name)).get(index).ok_or_else(|| format!("{name}[{index}] does not exist"))
+  }
+
+  // nodes of the default scene, or every node that is no other's child without scenes
+  fn roots(&self) -> Result<Vec<usize>, String> {
+    let scenes = array(self.json.get("scenes"));
+    if scenes.is_empty() {
+      let nodes = array(self.json.get("nodes"));
+      let mut child = vec![false; nodes.len()];
+      for index in nodes.iter().flat_map(|node| indices(node.get("children"))) {
+        if let Some(flag) = child.get_mut(index) {
+          *flag = true;
+        }
+      }
+      return Ok((0..nodes.len()).filter(|&index| !child[index]).collect());
+    }
+
+    let index = self.json.get("scene").and_then(Json::as_usize).unwrap_or(0);
+    Ok(indices(self.item("scenes", index)?.get("nodes")))
+  }
+
+  fn visit(
+    &self,
+    index: usize,
+    parent: &Transform,
+    depth: usize,
+    materials: &[SharedMaterial],
+    walk: &mut Walk,
+    (image_width, aspect_ratio): (usize, f64),
+  ) -> Result<(), String> {
+    if depth > MAX_NODE_DEPTH {
+      return Err("node hierarchy is too deep or has a cycle".to_string());
+    }
+    let node = self.item("nodes", index)?;
+    // a flattened node hides everything below it
+    let Some(local) = node_transform(node)? else {
+      return Ok(());
+    };
+    let world = local.then(parent);
+
+    if let Some(mesh) = node.get("mesh").and_then(Json::as_usize) {
+      if let Entry::Vacant(entry) = walk.meshes.entry(mesh) {
+        entry.insert(self.mesh(mesh, materials)?);
+      }
+      if let Some(loaded) = &walk.meshes[&mesh] {
+        let object = if loaded.emissive {
+          Object::Mesh(Mesh::new(loaded.mesh.triangles().iter().map(|t| transformed(t, &world)).collect()))
+        } else {
+          let shared: Arc<dyn Hittable + Send + Sync> = loaded.mesh.clone();
+          Object::Instance(Instance::new(shared, world, None))
+        };
+        walk.objects.push(object);
+      }
+    }
+
+    if let Some(camera) = node.get("camera").and_then(Json::as_usize)
+      && walk.camera.is_none() {
+      walk.camera = self.camera(camera, &world, image_width, aspect_ratio)?;
+    }
+
+    let light = node.get("extensions").and_then(|e| e.get("KHR_lights_punctual")).and_then(|e| e.get("light"));
+    if let Some(light) = light.and_then(Json::as_usize) {
+      walk.lights.push(self.light(light, &world)?);
+    }
+
+    for child in indices(node.get("children")) {
+      self.visit(child, &world, depth + 1, materials, walk, (image_width, aspect_ratio))?;
+    }
+    Ok(())
+  }
+
+  // None for meshes without any triangle
+  fn mesh(&self, index: usize, materials: &[SharedMaterial]) -> Result<Option<LoadedMesh>, String> {
+    let mesh = self.item("meshes", index)?;
+    let mut triangles = Vec::new();
+
+    for primitive in array(mesh.get("primitives")) {
+      let mode = primitive.get("mode").and_then(Json::as_usize).unwrap_or(TRIANGLES);
+      if !matches!(mode, TRIANGLES | TRIANGLE_STRIP | TRIANGLE_FAN) {
+        continue;
+      }
+      let attribute = |name| primitive.get("attributes").and_then(|a| a.get(name)).and_then(Json::as_usize);
+      let Some(positions) = attribute("POSITION") else {
+        continue;
+      };
+      let positions = self.accessor::<3>(positions, None)?;
+      let normals = attribute("NORMAL").map(|a| self.accessor::<3>(a, Some(positions.len()))).transpose()?;
+      let uvs = attribute("TEXCOORD_0").map(|a| self.accessor::<2>(a, Some(positions.len()))).transpose()?;
+
+      let vertices = match primitive.get("indices").and_then(Json::as_usize) {
+        Some(accessor) => self.accessor::<1>(accessor, None)?.into_iter().map(|[i]| i as usize).collect(),
+        None => (0..positions.len()).collect::<Vec<_>>(),
+      };
+      let count = positions.len();
+      if vertices.iter().any(|&i| i >= count)
+        || normals.as_ref().is_some_and(|n| n.len() < count)
+        || uvs.as_ref().is_some_and(|uv| uv.len() < count) {
+        return Err(format!("meshes[{index}] refers to vertices it does not have"));
+      }
+
+      let material = match primitive.get("material").and_then(Json::as_usize) {
+        Some(material) => Arc::clone(materials.get(material).ok_or_else(|| format!("materials[{material}] does not exist"))?),
+        None => default_material(),
+      };
+
+      for corners in faces(mode, &vertices) {
+        let vertices = corners.map(|i| vector(positions[i]));
+        if (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).length_squared() == 0.0 {
+          continue;
+        }
+        // normals that cannot be normalized leave the triangle flat
+        let normals = normals.as_ref()
+          .map(|n| corners.map(|i| vector(n[i])))
+          .filter(|n| n.iter().all(|n| n.length_squared() > 0.0))
+          .map(|n| n.map(|n| n.unit()));
+        // glTF puts v = 0 at the top of images, textures expect it at the bottom
+        let uvs = uvs.as_ref().map(|uv| corners.map(|i| (uv[i][0], 1.0 - uv[i][1])));
+        triangles.push(Triangle { vertices, normals, uvs, material: Arc::clone(&material) });
+      }
+    }
+
+    if triangles.is_empty() {
+      return Ok(None);
+    }
+    let emissive = triangles.iter().any(|t| t.material.is_emissive());
+    Ok(Some(LoadedMesh { mesh: Arc::new(Mesh::new(triangles)), emissive }))
+  }
+
+  fn material(&self, index: usize, textures: &mut TextureCache) -> Result<SharedMaterial, String> {
+    let material = self.item("materials", index)?;
+    let extension = |name| material.get("extensions").and_then(|e| e.get(name));
+    let pbr = material.get("pbrMetallicRoughness");
+    let factor = |name| pbr.and_then(|p| p.get(name));
+
+    let [r, g, b, _] = factor("baseColorFactor").and_then(Json::as_numbers::<4>).unwrap_or([1.0; 4]);
+    let base_color = self.tinted(pbr.and_then(|p| p.get("baseColorTexture")), Vec3::new(r, g, b), textures)?;
+
+    let [r, g, b] = material.get("emissiveFactor").and_then(Json::as_numbers::<3>).unwrap_or([0.0; 3]);
+    if r.max(g).max(b) > 0.0 {
+      let strength = extension("KHR_materials_emissive_strength")
+        .and_then(|e| e.get("emissiveStrength"))
+        .and_then(Json::as_f64)
+        .unwrap_or(1.0);
+      let color = self.tinted(material.get("emissiveTexture"), Vec3::new(r, g, b), textures)?;
+      return Ok(Arc::new(DiffuseLight { color, intensity: strength }));
+    }
+
+    let transmission = extension("KHR_materials_transmission").and_then(|e| e.get("transmissionFactor")).and_then(Json::as_f64);
+    if transmission.is_some_and(|t| t > 0.0) {
+      let ior = extension("KHR_materials_ior").and_then(|e| e.get("ior")).and_then(Json::as_f64).unwrap_or(1.5);
+      return Ok(Arc::new(Dielectric { albedo: base_color, refraction_index: ior.max(1.0) }));
+    }
+
+    let metallic  = factor("metallicFactor").and_then(Json::as_f64).unwrap_or(1.0).clamp(0.0, 1.0);
+    let roughness = factor("roughnessFactor").and_then(Json::as_f64).unwrap_or(1.0).clamp(0.0, 1.0);
+    let metallic_roughness = pbr
+      .and_then(|p| p.get("metallicRoughnessTexture"))
+      .map(|info| self.texture(info, Encoding::Linear, textures))
+      .transpose()?;
+    Ok(Arc::new(MetallicRoughness { base_color, metallic, roughness, metallic_roughness }))
+  }
+
+  // an sRGB texture multiplied by `factor`, or only the factor
+  fn tinted(&self, info: Option<&Json>, factor: Vec3, textures: &mut TextureCache) -> Result<SharedTexture, String> {
+    let Some(info) = info else {
+      return Ok(constant(Color::Rgb(factor)));
+    };
+    let texture = self.texture(info, Encoding::Srgb, textures)?;
+    if factor == Vec3::new(1.0, 1.0, 1.0) {
+      return Ok(texture);
+    }
+    Ok(Arc::new(Tint { texture, factor }))
+  }
+
+  // the image a texture info refers to, decoded once per texture and encoding
+  fn texture(&self, info: &Json, encoding: Encoding, textures: &mut TextureCache) -> Result<SharedTexture, String> {
+    let index = info.get("index").and_then(Json::as_usize).ok_or("texture reference without an index")?;
+    if let Some(texture) = textures.get(&(index, encoding)) {
+      return Ok(Arc::clone(texture));
+    }
+
+    let texture = self.item("textures", index)?;
+    let source = texture.get("source").and_then(Json::as_usize).ok_or_else(|| format!("textures[{index}] has no png or jpeg source"))?;
+    // a single wrap mode covers both axes, the horizontal one is used
+    let wrap = match texture.get("sampler").and_then(Json::as_usize) {
+      Some(sampler) => match self.item("samplers", sampler)?.get("wrapS").and_then(Json::as_usize) {
+        Some(33071) => WrapMode::Clamp,
+        Some(33648) => WrapMode::Mirror,
+        _ => WrapMode::Repeat,
+      },
+      None => WrapMode::Repeat,
+    };
+
+    let image = self.item("images", source)?;
+    let bytes = match (image.get("uri").and_then(Json::as_str), image.get("bufferView").and_then(Json::as_usize)) {
+      (Some(uri), _) => read_uri(self.base_dir, uri)?,
+      (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+      (None, None) => return Err(format!("images[{source}] has neither a uri nor a buffer view")),
+    };
+    let decoded: SharedTexture = Arc::new(
+      ImageTexture::decode(&bytes, wrap, encoding).map_err(|e| format!("images[{source}]: {e}"))?,
+    );
+    textures.insert((index, encoding), Arc::clone(&decoded));
+    Ok(decoded)
+  }
+
+  // bytes of a buffer view and the distance between its elements when it is interleaved
+  fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+    let view = self.item("bufferViews", index)?;
+    let error = || format!("bufferViews[{index}] lies outside its buffer");
+    let buffer = view.get("buffer").and_then(Json::as_usize).and_then(|b| self.buffers.get(b)).ok_or_else(error)?;
+    let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+    let length = view.get("byteLength").and_then(Json::as_usize).ok_or_else(error)?;
+    let bytes = buffer.get(offset..offset + length).ok_or_else(error)?;
+    Ok((bytes, view.get("byteStride").and_then(Json::as_usize)))
+  }
+
+  // elements of an accessor with N components each, normalized integers are brought
+  // into [0, 1] or [-1, 1]. `vertices` is the vertex count for per-vertex attributes,
+  // None for positions and indices
+  fn accessor<const N: usize>(&self, index: usize, vertices: Option<usize>) -> Result<Vec<[f64; N]>, String> {
+    let accessor = self.item("accessors", index)?;
+    let components = match accessor.get("type").and_then(Json::as_str) {
+      Some("SCALAR") => 1,
+      Some("VEC2")   => 2,
+      Some("VEC3")   => 3,
+      Some("VEC4")   => 4,
+      _ => 0,
+    };
+    if components != N {
+      return Err(format!("accessors[{index}] does not hold {N} component elements"));
+    }
+    if accessor.get("sparse").is_some() {
+      return Err(format!("accessors[{index}] is sparse, sparse accessors are not supported"));
+    }
+
+    let count = accessor.get("count").and_then(Json::as_usize).ok_or_else(|| format!("accessors[{index}] has no count"))?;
+    let component_type = accessor.get("componentType").and_then(Json::as_usize).unwrap_or(0);
+    let size = match component_type {
+      5120 | 5121 => 1, // signed and unsigned byte
+      5122 | 5123 => 2, // signed and unsigned short
+      5125 | 5126 => 4, // unsigned int and float
+      other => return Err(format!("accessors[{index}] has unknown component type {other}")),
+    };
+    let normalized = matches!(accessor.get("normalized"), Some(Json::Bool(true)));
+
+    // accessors without a buffer view are all zeros, nothing in the file backs their count
+    // so they are only taken for attributes and never longer than the positions
+    let Some(view) = accessor.get("bufferView").and_then(Json::as_usize) else {
+      return match vertices {
+        Some(vertices) if count <= vertices => Ok(vec![[0.0; N]; count]),
+        _ => Err(format!("accessors[{index}] has no buffer view")),
+      };
+    };
+    let (bytes, stride) = self.buffer_view(view)?;
+    let offset = accessor.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+    let element = size * N;
+    let stride = stride.unwrap_or(element);
+    let end = stride.checked_mul(count.saturating_sub(1))
+      .and_then(|n| n.checked_add(offset))
+      .and_then(|n| n.checked_add(element));
+    if count > 0 && end.is_none_or(|end| end > bytes.len()) {
+      return Err(format!("accessors[{index}] runs past its buffer view"));
+    }
+
+    let read = |at: usize| -> f64 {
+      let b = &bytes[at..at + size];
+      match (component_type, normalized) {
+        (5120, true)  => (b[0] as i8 as f64 / 127.0).max(-1.0),
+        (5120, false) => b[0] as i8 as f64,
+        (5121, true)  => b[0] as f64 / 255.0,
+        (5121, false) => b[0] as f64,
+        (5122, true)  => (i16::from_le_bytes([b[0], b[1]]) as f64 / 32767.0).max(-1.0),
+        (5122, false) => i16::from_le_bytes([b[0], b[1]]) as f64,
+        (5123, true)  => u16::from_le_bytes([b[0], b[1]]) as f64 / 65535.0,
+        (5123, false) => u16::from_le_bytes([b[0], b[1]]) as f64,
+        (5125, _)     => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        _             => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+      }
+    };
+    Ok((0..count).map(|i| std::array::from_fn(|c| read(offset + i * stride + c * size))).collect())
+  }
+
+  // None for orthographic cameras
+  fn camera(&self, index: usize, world: &Transform, image_width: usize, aspect_ratio: f64) -> Result<Option<Camera>, String> {
+    let Some(perspective) = self.item("cameras", index)?.get("perspective") else {
+      return Ok(None);
+    };
+    let yfov = perspective.get("yfov").and_then(Json::as_f64).filter(|f| *f > 0.0 && *f < PI);
+    let yfov = yfov.ok_or_else(|| format!("cameras[{index}] has no valid vertical field of view"))?;
+
+    // cameras look down their -z axis with +y up
+    let position = world.point(Point3::zero());
+    let forward  = world.vector(Vec3::new(0.0, 0.0, -1.0));
+    let up       = world.vector(Vec3::new(0.0, 1.0, 0.0));
+    Ok(Some(Camera::look_at(position, position + forward, up, yfov.to_degrees(), aspect_ratio, image_width)))
+  }
+
+  fn light(&self, index: usize, world: &Transform) -> Result<Light, String> {
+    let lights = self.json.get("extensions").and_then(|e| e.get("KHR_lights_punctual")).and_then(|e| e.get("lights"));
+    let light = array(lights).get(index).ok_or_else(|| format!("lights[{index}] does not exist"))?;
+
+    let [r, g, b] = light.get("color").and_then(Json::as_numbers::<3>).unwrap_or([1.0; 3]);
+    let color     = Color::Rgb(Vec3::new(r, g, b));
+    let intensity = light.get("intensity").and_then(Json::as_f64).unwrap_or(1.0) / LUMENS_PER_WATT;
+    // lights shine down their -z axis
+    let position  = world.point(Point3::zero());
+    let direction = world.vector(Vec3::new(0.0, 0.0, -1.0)).unit();
+
+    match light.get("type").and_then(Json::as_str) {
+      Some("point") => Ok(Light::Point(PointLight { position, color, intensity })),
+      Some("directional") => Ok(Light::Directional(DirectionalLight { direction, color, intensity })),
+      Some("spot") => {
+        let angle = |name| light.get("spot").and_then(|s| s.get(name)).and_then(Json::as_f64);
+        let inner = angle("innerConeAngle").unwrap_or(0.0);
+        let outer = angle("outerConeAngle").unwrap_or(PI / 4.0);
+        Ok(Light::Spot(SpotLight {
+          position,
+          direction,
+          color,
+          intensity,
+          inner_angle: inner.to_degrees(),
+          outer_angle: outer.to_degrees(),
+        }))
+      }
+      _ => Err(format!("lights[{index}] has an unknown type")),
+    }
+  }
+}
+
+// decoded textures by texture index and encoding
+type TextureCache = HashMap<(usize, Encoding), SharedTexture>;
+
+// glTF's material for primitives without one, a rough white metal
+fn default_material() -> SharedMaterial {
+  Arc::new(MetallicRoughness {
+    base_color: constant(Color::Rgb(Vec3::new(1.0, 1.0, 1.0))),
+    metallic: 1.0,
+    roughness: 1.0,
+    metallic_roughness: None,
+  })
+}
+
+fn array(value: Option<&Json>) -> &[Json] {
+  value.and_then(Json::as_array).unwrap_or(&[])
+}
+
+fn indices(value: Option<&Json>) -> Vec<usize> {
+  array(value).iter().filter_map(Json::as_usize).collect()
+}
+
+// optional node property of N numbers
+fn numbers<const N: usize>(node: &Json, name: &str, default: [f64; N]) -> Result<[f64; N], String> {
+  match node.get(name) {
+    Some(value) => value.as_numbers::<N>().ok_or_else(|| format!("node {name} does not hold {N} numbers")),
+    None => Ok(default),
+  }
+}
+
+fn vector([x, y, z]: [f64; 3]) -> Vec3 {
+  Vec3::new(x, y, z)
+}
+
+// vertex triples of the triangles making up a primitive, strips flip every other
+// triangle to keep the winding consistent
+fn faces(mode: usize, vertices: &[usize]) -> Vec<[usize; 3]> {
+  match mode {
+    TRIANGLE_STRIP => vertices
+      .windows(3)
+      .enumerate()
+      .map(|(i, w)| if i % 2 == 0 { [w[0], w[1], w[2]] } else { [w[1], w[0], w[2]] })
+      .collect(),
+    TRIANGLE_FAN => (1..vertices.len().saturating_sub(1))
+      .map(|i| [vertices[0], vertices[i], vertices[i + 1]])
+      .collect(),
+    _ => vertices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+  }
+}
+
+// a node's local transform from its matrix or its translation, rotation and scale,
+// None when it flattens everything (a zero scale)
+fn node_transform(node: &Json) -> Result<Option<Transform>, String> {
+  if let Some(matrix) = node.get("matrix") {
+    let m = matrix.as_numbers::<16>().ok_or("node matrix does not hold 16 numbers")?;
+    // stored column by column
+    let rows: Matrix = std::array::from_fn(|i| std::array::from_fn(|j| m[j * 4 + i]));
+    return Ok(Transform::from_matrix(rows));
+  }
+
+  let [tx, ty, tz] = numbers(node, "translation", [0.0; 3])?;
+  let [x, y, z, w] = numbers(node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+  let [sx, sy, sz] = numbers(node, "scale", [1.0; 3])?;
+
+  // translation * rotation * scale, with the rotation from the unit quaternion
+  let length = (x * x + y * y + z * z + w * w).sqrt();
+  let (x, y, z, w) = if length > 0.0 { (x / length, y / length, z / length, w / length) } else { (0.0, 0.0, 0.0, 1.0) };
+  let rotation = [
+    [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w),       2.0 * (x * z + y * w)      ],
+    [2.0 * (x * y + z * w),       1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w)      ],
+    [2.0 * (x * z - y * w),       2.0 * (y * z + x * w),       1.0 - 2.0 * (x * x + y * y)],
+  ];
+  let (scale, translation) = ([sx, sy, sz], [tx, ty, tz]);
+  let mut matrix: Matrix = [[0.0; 4]; 4];
+  for i in 0..3 {
+    for j in 0..3 {
+      matrix[i][j] = rotation[i][j] * scale[j];
+    }
+    matrix[i][3] = translation[i];
+  }
+  Ok(Transform::from_matrix(matrix))
+}
+
+// a triangle moved into world space, for meshes lighting the scene
+fn transformed(triangle: &Triangle, to_world: &Transform) -> Triangle {
+  Triangle {
+    vertices: triangle.vertices.map(|v| to_world.point(v)),
+    normals : triangle.normals.map(|normals| normals.map(|n| to_world.normal(n).unit())),
+    uvs     : triangle.uvs,
+    material: Arc::clone(&triangle.material),
+  }
+}
+
+// the JSON text and binary chunk of a glb file
+fn split_glb(bytes: &[u8]) -> Result<(&str, Option<Vec<u8>>), String> {
+  let word = |at: usize| {
+    bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize).ok_or("truncated glb file")
+  };
+  if word(4)? != 2 {
+    return Err("only version 2 glb files are supported".to_string());
+  }
+
+  let end = word(8)?.min(bytes.len());
+  let (mut json, mut binary) = (None, None);
+  let mut at = 12;
+  while at + 8 <= end {
+    let (length, kind) = (word(at)?, word(at + 4)?);
+    // chunks stay within the length the header gives for the whole file
+    let chunk = bytes[..end].get(at + 8..at + 8 + length).ok_or("truncated glb chunk")?;
+    match kind {
+      0x4e4f534a if json.is_none()   => json = Some(chunk),           // "JSON"
+      0x004e4942 if binary.is_none() => binary = Some(chunk.to_vec()), // "BIN\0"
+      _ => {}
+    }
+    at += 8 + length;
+  }
+
+  let json = json.ok_or("glb file has no JSON chunk")?;
+  let text = std::str::from_utf8(json).map_err(|_| "glb JSON chunk is not UTF-8")?;
+  Ok((text, binary))
+}
+
+// contents of a base64 data uri or of a file relative to the glTF file
+fn read_uri(base_dir: &Path, uri: &str) -> Result<Vec<u8>, String> {
+  if let Some(data) = uri.strip_prefix("data:") {
+    let (_, payload) = data.split_once(";base64,").ok_or("only base64 data uris are supported")?;
+    return base64(payload);
+  }
+  let path = base_dir.join(percent_decode(uri));
+  fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+// uris escape spaces and other characters as %XX
+fn percent_decode(uri: &str) -> String {
+  let bytes = uri.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = (bytes[i] == b'%')
+      .then(|| bytes.get(i + 1..i + 3))
+      .flatten()
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match escaped {
+      Some(byte) => {
+        out.push(byte);
+        i += 3;
+      }
+      None => {
+        out.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, String> {
+  let mut out = Vec::with_capacity(text.len() / 4 * 3);
+  let (mut bits, mut count) = (0u32, 0);
+  for byte in text.bytes().take_while(|&b| b != b'=') {
+    let value = match byte {
+      b'A'..=b'Z' => byte - b'A',
+      b'a'..=b'z' => byte - b'a' + 26,
+      b'0'..=b'9' => byte - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      _ => return Err("invalid base64 data".to_string()),
+    };
+    bits = (bits << 6 | value as u32) & 0xffff;
+    count += 6;
+    if count >= 8 {
+      count -= 8;
+      out.push((bits >> count) as u8);
+    }
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_matrix_eq(a: &Matrix, b: &Matrix) {
+    for (row_a, row_b) in a.iter().zip(b) {
+      for (x, y) in row_a.iter().zip(row_b) {
+        assert!((x - y).abs() < 1e-9, "{a:?} != {b:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn base64_with_and_without_padding() {
+    assert_eq!(base64("TWFu").unwrap(), b"Man");
+    assert_eq!(base64("TWE=").unwrap(), b"Ma");
+    assert_eq!(base64("TWE").unwrap(), b"Ma");
+    assert_eq!(base64("TQ==").unwrap(), b"M");
+    assert_eq!(base64("TQ").unwrap(), b"M");
+    assert_eq!(base64("").unwrap(), b"");
+    assert_eq!(base64("+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+    assert!(base64("TW-u").is_err());
+  }
+
+  #[test]
+  fn percent_decoding() {
+    assert_eq!(percent_decode("mr%20map.png"), "mr map.png");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%zz"), "%zz");
+  }
+
+  // header, then a JSON chunk and a binary one with the given lengths
+  fn glb(json: &[u8], binary: &[u8], total: Option<usize>) -> Vec<u8> {
+    let mut chunks = Vec::new();
+    for (kind, data) in [(0x4e4f534au32, json), (0x004e4942, binary)] {
+      chunks.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      chunks.extend_from_slice(&kind.to_le_bytes());
+      chunks.extend_from_slice(data);
+    }
+    let total = total.unwrap_or(12 + chunks.len());
+    let mut bytes = b"glTF".to_vec();
+    bytes.extend_from_slice(&2u32.to_le_bytes());
+    bytes.extend_from_slice(&(total as u32).to_le_bytes());
+    bytes.extend_from_slice(&chunks);
+    bytes
+  }
+
+  #[test]
+  fn glb_chunks() {
+    let bytes = glb(b"{}  ", &[1, 2, 3, 4], None);
+    let (json, binary) = split_glb(&bytes).unwrap();
+    assert_eq!(json, "{}  ");
+    assert_eq!(binary.unwrap(), [1, 2, 3, 4]);
+
+    let (_, binary) = split_glb(&glb(b"{}  ", &[], None)).unwrap();
+    assert_eq!(binary.unwrap(), []);
+  }
+
+  #[test]
+  fn glb_truncated_or_overlong_chunk() {
+    // the binary chunk claims more bytes than the file holds
+    let mut bytes = glb(b"{}  ", &[1, 2, 3, 4], None);
+    bytes.truncate(bytes.len() - 2);
+    assert!(split_glb(&bytes).is_err());
+
+    // the binary chunk runs past the length in the header
+    let bytes = glb(b"{}  ", &[1, 2, 3, 4], Some(12 + 12 + 8 + 2));
+    assert!(split_glb(&bytes).is_err());
+
+    // a chunk length that overflows the file
+    let mut bytes = glb(b"{}  ", &[], None);
+    bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(split_glb(&bytes).is_err());
+
+    assert!(split_glb(b"glTF\x02\0\0").is_err());
+    assert!(split_glb(&glb(b"{}  ", &[], None)[..12]).is_err());
+  }
+
+  #[test]
+  fn glb_version() {
+    let mut bytes = glb(b"{}  ", &[], None);
+    bytes[4] = 1;
+    assert!(split_glb(&bytes).is_err());
+  }
+
+  #[test]
+  fn node_trs_matches_transforms() {
+    // 90 degrees around y
+    let half = std::f64::consts::FRAC_PI_4;
+    let node = Json::parse(&format!(
+      r#"{{ "translation": [1, 2, 3], "rotation": [0, {}, 0, {}], "scale": [2, 3, 4] }}"#,
+      half.sin(), half.cos(),
+    )).unwrap();
+    let expected = Transform::scale(Vec3::new(2.0, 3.0, 4.0))
+      .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), 90.0))
+      .then(&Transform::translate(Vec3::new(1.0, 2.0, 3.0)));
+
+    let transform = node_transform(&node).unwrap().unwrap();
+    assert_matrix_eq(&transform.matrix, &expected.matrix);
+    assert_matrix_eq(&transform.inverse, &expected.inverse);
+
+    let identity = node_transform(&Json::parse("{}").unwrap()).unwrap().unwrap();
+    assert_matrix_eq(&identity.matrix, &Transform::identity().matrix);
+  }
+
+  #[test]
+  fn node_matrix_is_column_major() {
+    let node = Json::parse(r#"{ "matrix": [1, 0, 0, 0,  0, 1, 0, 0,  0, 0, 1, 0,  5, 6, 7, 1] }"#).unwrap();
+    let transform = node_transform(&node).unwrap().unwrap();
+    assert_matrix_eq(&transform.matrix, &Transform::translate(Vec3::new(5.0, 6.0, 7.0)).matrix);
+
+    let singular = Json::parse(r#"{ "scale": [1, 0, 1] }"#).unwrap();
+    assert!(node_transform(&singular).unwrap().is_none());
+    assert!(node_transform(&Json::parse(r#"{ "rotation": [0, 0, 1] }"#).unwrap()).is_err());
+  }
+
+  #[test]
+  fn accessor_counts_are_backed_by_data() {
+    let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter().flat_map(|f| f.to_le_bytes()).collect();
+    let json = br#"{
+      "asset": { "version": "2.0" },
+      "buffers": [{ "byteLength": 36 }],
+      "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+      "accessors": [
+        { "bufferView": 0, "componentType": 5126, "type": "VEC3", "count": 3 },
+        { "componentType": 5126, "type": "VEC3", "count": 3 },
+        { "componentType": 5126, "type": "VEC3", "count": 4294967295 },
+        { "componentType": 5125, "type": "SCALAR", "count": 4294967295 },
+        { "bufferView": 0, "byteOffset": 4, "componentType": 5126, "type": "VEC3", "count": 4294967295 }
+      ]
+    }"#;
+    let document = Document::read(&glb(json, &positions, None), Path::new(".")).unwrap();
+
+    assert_eq!(document.accessor::<3>(0, None).unwrap()[1], [1.0, 0.0, 0.0]);
+    // zeroed normals for the stored positions
+    assert_eq!(document.accessor::<3>(1, Some(3)).unwrap(), [[0.0; 3]; 3]);
+    assert_eq!(document.accessor::<3>(1, None).err().unwrap(), "accessors[1] has no buffer view");
+    assert_eq!(document.accessor::<3>(2, Some(3)).err().unwrap(), "accessors[2] has no buffer view");
+    assert_eq!(document.accessor::<1>(3, None).err().unwrap(), "accessors[3] has no buffer view");
+    assert_eq!(document.accessor::<3>(4, None).err().unwrap(), "accessors[4] runs past its buffer view");
+  }
+}
@@ -0,0 +1,166 @@
+// light transport algorithms turning camera rays into radiance
+
+use crate::math::Vec3;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::{ offset_origin, Scene, RAY_BIAS };
+use crate::scene::object::HitRecord;
+
+// bounces always traced before russian roulette may end a path
+pub const DEFAULT_ROULETTE_DEPTH: u32 = 3;
+// highest chance of a path surviving russian roulette, below 1 so paths trapped
+// between lossless surfaces still end
+const MAX_SURVIVAL: f64 = 0.95;
+// how far from the sampled point of an area light a shadow ray may stop, relative to
+// the distance to that point, and still count as reaching it
+const TARGET_TOLERANCE: f64 = 1e-6;
+
+pub trait Integrator {
+  // radiance arriving at the origin of `ray` from its direction
+  fn radiance(&self, scene: &Scene, ray: &Ray, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+// unidirectional path tracer with next event estimation: every bounce samples the lights
+// directly, and area lights and environment maps combine their samples with the bsdf
+// ones by multiple importance sampling
+pub struct PathTracer {
+  pub roulette_depth: u32,
+}
+
+impl Default for PathTracer {
+  fn default() -> Self {
+    PathTracer { roulette_depth: DEFAULT_ROULETTE_DEPTH }
+  }
+}
+
+impl Integrator for PathTracer {
+  fn radiance(&self, scene: &Scene, ray: &Ray, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut radiance   = Vec3::zero();
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut ray        = *ray;
+    // how the current ray was picked, camera rays count as specular since no light
+    // sample competes with them
+    let mut specular   = true;
+    let mut pdf        = 0.0;
+
+    for bounce in 0.. {
+      let Some((object, hit)) = scene.bvh().cast(&scene.objects, &ray) else {
+        let weight = if specular { 1.0 } else { power_heuristic(pdf, scene.environment.pdf(&ray.dir)) };
+        radiance += throughput * scene.environment.radiance(&ray.dir) * weight;
+        break;
+      };
+
+      // emitters the previous bounce ran into, weighted against the chance that
+      // sampling the light would have found the same point
+      let emitted = hit.material.emitted(&ray, &hit);
+      if emitted != Vec3::zero() {
+        let weight = match scene.area_light(object) {
+          Some(light) if !specular => power_heuristic(pdf, light.pdf(ray.o, &hit)),
+          _ => 1.0,
+        };
+        radiance += throughput * emitted * weight;
+      }
+
+      // the ambient term stands in for indirect light, which paths gather themselves
+      // once they have bounced off a surface that is not a mirror
+      if specular {
+        radiance += throughput * scene.ambient_light(&hit);
+      }
+      radiance += throughput * (
+        scene.direct_light(&ray, &hit)
+        + self.area_lighting(scene, &ray, &hit, sampler)
+        + self.environment_lighting(scene, &ray, &hit, sampler)
+      );
+
+      let Some(sample) = hit.material.scatter(&ray, &hit, sampler) else {
+        break;
+      };
+      throughput *= sample.throughput;
+      specular = sample.specular;
+      pdf      = sample.pdf;
+      ray      = Ray::new(offset_origin(&hit, &sample.dir), sample.dir);
+
+      // unbiased termination, paths carrying little light are the likeliest to end
+      // and the survivors make up for them
+      if bounce + 1 >= self.roulette_depth {
+        let survival = throughput.x.max(throughput.y).max(throughput.z).min(MAX_SURVIVAL);
+        if sampler.get_1d() >= survival {
+          break;
+        }
+        throughput /= survival;
+      }
+    }
+
+    radiance
+  }
+}
+
+impl PathTracer {
+  // one sample of every area light, a shadow ray that stops anywhere else than the
+  // sampled point is occluded, even by another part of the same light
+  fn area_lighting(&self, scene: &Scene, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut total = Vec3::zero();
+    for light in scene.area_lights() {
+      // every light takes its dimensions whether it contributes or not, so the
+      // sampler dimensions stay aligned across samples
+      let (uv, pick) = (sampler.get_2d(), sampler.get_1d());
+      let origin = hit.point + hit.normal * RAY_BIAS;
+      let Some((dir, distance, light_pdf)) = light.sample(origin, uv, pick) else {
+        continue;
+      };
+
+      let cos_theta = hit.normal.dot(&dir);
+      if cos_theta <= 0.0 {
+        continue;
+      }
+      let bsdf = hit.material.eval(ray, hit, &dir);
+      if bsdf == Vec3::zero() {
+        continue;
+      }
+
+      let shadow = Ray::new(origin, dir);
+      let Some((object, light_hit)) = scene.bvh().cast(&scene.objects, &shadow) else {
+        continue;
+      };
+      if object != light.object || (light_hit.t - distance).abs() > TARGET_TOLERANCE * distance {
+        continue;
+      }
+
+      let emitted = light_hit.material.emitted(&shadow, &light_hit);
+      let weight  = power_heuristic(light_pdf, hit.material.pdf(ray, hit, &dir));
+      total += bsdf * emitted * (cos_theta * weight / light_pdf);
+    }
+    total
+  }
+
+  // one sample of the environment, the shadow ray has to leave the scene
+  fn environment_lighting(&self, scene: &Scene, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Vec3 {
+    let uv = sampler.get_2d();
+    let Some((dir, emitted, light_pdf)) = scene.environment.sample(uv) else {
+      return Vec3::zero();
+    };
+
+    let cos_theta = hit.normal.dot(&dir);
+    if cos_theta <= 0.0 {
+      return Vec3::zero();
+    }
+    let bsdf = hit.material.eval(ray, hit, &dir);
+    if bsdf == Vec3::zero() {
+      return Vec3::zero();
+    }
+
+    if scene.occluded(hit.point + hit.normal * RAY_BIAS, dir, f64::INFINITY) {
+      return Vec3::zero();
+    }
+
+    let weight = power_heuristic(light_pdf, hit.material.pdf(ray, hit, &dir));
+    bsdf * emitted * (cos_theta * weight / light_pdf)
+  }
+}
+
+// veach's power heuristic with an exponent of 2, weight of the strategy with density `a`
+fn power_heuristic(a: f64, b: f64) -> f64 {
+  let (a2, b2) = (a * a, b * b);
+  if a2 + b2 == 0.0 { 0.0 } else { a2 / (a2 + b2) }
+}
+
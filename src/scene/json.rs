@@ -0,0 +1,297 @@
+// minimal JSON reader for glTF documents
+//
+// numbers are all read as f64, which holds every index and count glTF files use,
+// object members keep the order they are written in
+
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+// deepest nesting accepted, keeps hostile files from overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+impl Json {
+  pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { bytes: text.as_bytes(), position: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.position < parser.bytes.len() {
+      return Err(parser.error("unexpected data after the document"));
+    }
+    Ok(value)
+  }
+
+  // member `key` of an object, None for missing members and other values
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      Json::Number(n) => Some(*n),
+      _ => None,
+    }
+  }
+
+  // non-negative integers, such as indices into the document's arrays
+  pub fn as_usize(&self) -> Option<usize> {
+    self.as_f64().filter(|n| *n >= 0.0 && n.fract() == 0.0 && *n <= u32::MAX as f64).map(|n| n as usize)
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(items) => Some(items),
+      _ => None,
+    }
+  }
+
+  // an array of exactly N numbers
+  pub fn as_numbers<const N: usize>(&self) -> Option<[f64; N]> {
+    let items = self.as_array()?;
+    if items.len() != N {
+      return None;
+    }
+    let mut numbers = [0.0; N];
+    for (number, item) in numbers.iter_mut().zip(items) {
+      *number = item.as_f64()?;
+    }
+    Some(numbers)
+  }
+}
+
+struct Parser<'a> {
+  bytes   : &'a [u8],
+  position: usize,
+}
+
+impl Parser<'_> {
+  fn error(&self, message: &str) -> String {
+    format!("invalid JSON at byte {}: {message}", self.position)
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.bytes.get(self.position).is_some_and(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r')) {
+      self.position += 1;
+    }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.bytes.get(self.position).copied()
+  }
+
+  fn expect(&mut self, literal: &str) -> Result<(), String> {
+    if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+      self.position += literal.len();
+      Ok(())
+    } else {
+      Err(self.error(&format!("expected `{literal}`")))
+    }
+  }
+
+  fn value(&mut self, depth: usize) -> Result<Json, String> {
+    if depth > MAX_DEPTH {
+      return Err(self.error("too deeply nested"));
+    }
+    self.skip_whitespace();
+    match self.peek() {
+      Some(b'{') => self.object(depth),
+      Some(b'[') => self.array(depth),
+      Some(b'"') => Ok(Json::String(self.string()?)),
+      Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+      Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+      Some(b'n') => self.expect("null").map(|_| Json::Null),
+      Some(b'-' | b'0'..=b'9') => self.number(),
+      Some(_) => Err(self.error("unexpected character")),
+      None => Err(self.error("unexpected end of document")),
+    }
+  }
+
+  fn object(&mut self, depth: usize) -> Result<Json, String> {
+    self.position += 1;
+    let mut members = Vec::new();
+    self.skip_whitespace();
+    if self.peek() == Some(b'}') {
+      self.position += 1;
+      return Ok(Json::Object(members));
+    }
+    loop {
+      self.skip_whitespace();
+      if self.peek() != Some(b'"') {
+        return Err(self.error("expected a member name"));
+      }
+      let key = self.string()?;
+      self.skip_whitespace();
+      self.expect(":")?;
+      members.push((key, self.value(depth + 1)?));
+      self.skip_whitespace();
+      match self.peek() {
+        Some(b',') => self.position += 1,
+        Some(b'}') => {
+          self.position += 1;
+          return Ok(Json::Object(members));
+        }
+        _ => return Err(self.error("expected `,` or `}`")),
+      }
+    }
+  }
+
+  fn array(&mut self, depth: usize) -> Result<Json, String> {
+    self.position += 1;
+    let mut items = Vec::new();
+    self.skip_whitespace();
+    if self.peek() == Some(b']') {
+      self.position += 1;
+      return Ok(Json::Array(items));
+    }
+    loop {
+      items.push(self.value(depth + 1)?);
+      self.skip_whitespace();
+      match self.peek() {
+        Some(b',') => self.position += 1,
+        Some(b']') => {
+          self.position += 1;
+          return Ok(Json::Array(items));
+        }
+        _ => return Err(self.error("expected `,` or `]`")),
+      }
+    }
+  }
+
+  fn number(&mut self) -> Result<Json, String> {
+    let start = self.position;
+    while self.peek().is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+      self.position += 1;
+    }
+    let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or("");
+    text.parse().map(Json::Number).map_err(|_| self.error("invalid number"))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.position += 1;
+    let mut out = Vec::new();
+    loop {
+      let Some(byte) = self.peek() else {
+        return Err(self.error("unterminated string"));
+      };
+      self.position += 1;
+      match byte {
+        b'"' => break,
+        b'\\' => {
+          let escaped = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+          self.position += 1;
+          let c = match escaped {
+            b'"'  => '"',
+            b'\\' => '\\',
+            b'/'  => '/',
+            b'b'  => '\u{8}',
+            b'f'  => '\u{c}',
+            b'n'  => '\n',
+            b'r'  => '\r',
+            b't'  => '\t',
+            b'u'  => self.unicode_escape()?,
+            _ => return Err(self.error("invalid escape")),
+          };
+          out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        _ => out.push(byte),
+      }
+    }
+    String::from_utf8(out).map_err(|_| self.error("invalid UTF-8 in string"))
+  }
+
+  // the digits of a \u escape, with the second half of a surrogate pair when needed
+  fn unicode_escape(&mut self) -> Result<char, String> {
+    let high = self.hex4()?;
+    let code = if (0xd800..0xdc00).contains(&high) {
+      self.expect("\\u")?;
+      let low = self.hex4()?;
+      if !(0xdc00..0xe000).contains(&low) {
+        return Err(self.error("invalid surrogate pair"));
+      }
+      0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+    } else {
+      high
+    };
+    char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let digits = self.bytes.get(self.position..self.position + 4).ok_or_else(|| self.error("unterminated string"))?;
+    let text = std::str::from_utf8(digits).map_err(|_| self.error("invalid unicode escape"))?;
+    let value = u32::from_str_radix(text, 16).map_err(|_| self.error("invalid unicode escape"))?;
+    self.position += 4;
+    Ok(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn string(text: &str) -> Result<String, String> {
+    Json::parse(text).map(|value| value.as_str().unwrap().to_string())
+  }
+
+  #[test]
+  fn values() {
+    let value = Json::parse(r#" { "a": [1, -2.5e1, true, false, null], "b": { "c": "d" } } "#).unwrap();
+    let a = value.get("a").and_then(Json::as_array).unwrap();
+    assert_eq!(a[0].as_usize(), Some(1));
+    assert_eq!(a[1].as_f64(), Some(-25.0));
+    assert_eq!(a[1].as_usize(), None);
+    assert!(matches!(a[2..], [Json::Bool(true), Json::Bool(false), Json::Null]));
+    assert_eq!(value.get("b").and_then(|b| b.get("c")).and_then(Json::as_str), Some("d"));
+    assert!(value.get("e").is_none());
+    assert_eq!(Json::parse("[1, 2, 3]").unwrap().as_numbers::<3>(), Some([1.0, 2.0, 3.0]));
+    assert_eq!(Json::parse("[1, 2]").unwrap().as_numbers::<3>(), None);
+  }
+
+  #[test]
+  fn escapes() {
+    assert_eq!(string(r#""a\"b\\c\/d""#).unwrap(), "a\"b\\c/d");
+    assert_eq!(string(r#""\b\f\n\r\t""#).unwrap(), "\u{8}\u{c}\n\r\t");
+    assert_eq!(string(r#""\u00e9\u20AC""#).unwrap(), "é€");
+    assert_eq!(string("\"é\"").unwrap(), "é");
+    assert!(string(r#""\x""#).is_err());
+    assert!(string(r#""\u12""#).is_err());
+    assert!(string(r#""abc"#).is_err());
+  }
+
+  #[test]
+  fn surrogate_pairs() {
+    assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "\u{1f600}");
+    assert!(string(r#""\ud83d""#).is_err());
+    assert!(string(r#""\ud83d\u0041""#).is_err());
+    assert!(string(r#""\ude00""#).is_err());
+  }
+
+  #[test]
+  fn depth_limit() {
+    let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+    assert!(Json::parse(&nested(MAX_DEPTH + 2)).is_err());
+    assert!(Json::parse(&nested(100_000)).is_err());
+  }
+
+  #[test]
+  fn invalid_documents() {
+    for text in ["", "[1,]", "{\"a\" 1}", "[1] 2", "tru", "{1: 2}", "-", "[1 2]"] {
+      assert!(Json::parse(text).is_err(), "{text}");
+    }
+  }
+}
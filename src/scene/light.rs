@@ -0,0 +1,219 @@
+use std::f64::consts::PI;
+
+use crate::color::Color;
+use crate::math::{ Point3, Vec3, EPSILON };
+use crate::scene::object::{ HitRecord, Object, Triangle };
+
+// incoming light at a shaded point, as seen from that point
+pub struct LightSample {
+  pub dir     : Vec3, // unit vector from the shaded point towards the light
+  pub distance: f64,  // distance to the light, infinite for directional lights
+  pub radiance: Vec3, // color * intensity after falloff
+}
+
+// Light enum and variants
+
+pub enum Light {
+  Point(PointLight),
+  Directional(DirectionalLight),
+  Spot(SpotLight),
+}
+
+// emits equally in every direction, intensity falls off with the squared distance
+pub struct PointLight {
+  pub position : Point3,
+  pub color    : Color,
+  pub intensity: f64,
+}
+
+// infinitely far away light such as the sun, `direction` is where the light travels to
+pub struct DirectionalLight {
+  pub direction: Vec3,
+  pub color    : Color,
+  pub intensity: f64,
+}
+
+// point light restricted to a cone around `direction`,
+// fully lit inside `inner_angle` and fading out to `outer_angle` (degrees, half-angles)
+pub struct SpotLight {
+  pub position   : Point3,
+  pub direction  : Vec3,
+  pub color      : Color,
+  pub intensity  : f64,
+  pub inner_angle: f64,
+  pub outer_angle: f64,
+}
+
+impl Light {
+  // returns None when the light cannot reach `point` at all
+  pub fn sample(&self, point: Point3) -> Option<LightSample> {
+    match self {
+      Light::Point(l)       => l.sample(point),
+      Light::Directional(l) => l.sample(point),
+      Light::Spot(l)        => l.sample(point),
+    }
+  }
+}
+
+impl PointLight {
+  fn sample(&self, point: Point3) -> Option<LightSample> {
+    let to_light = self.position - point;
+    let distance_squared = to_light.length_squared();
+    if distance_squared < EPSILON {
+      return None;
+    }
+
+    let distance = distance_squared.sqrt();
+    Some(LightSample {
+      dir: to_light / distance,
+      distance,
+      radiance: Vec3::from(self.color) * (self.intensity / distance_squared),
+    })
+  }
+}
+
+impl DirectionalLight {
+  fn sample(&self, _point: Point3) -> Option<LightSample> {
+    Some(LightSample {
+      dir: -self.direction.unit(),
+      distance: f64::INFINITY,
+      radiance: Vec3::from(self.color) * self.intensity,
+    })
+  }
+}
+
+impl SpotLight {
+  fn sample(&self, point: Point3) -> Option<LightSample> {
+    let point_light = PointLight {
+      position : self.position,
+      color    : self.color,
+      intensity: self.intensity,
+    };
+    let mut sample = point_light.sample(point)?;
+
+    // smoothstep between the outer and inner cone
+    let cos_theta = (-sample.dir).dot(&self.direction.unit());
+    let cos_inner = self.inner_angle.to_radians().cos();
+    let cos_outer = self.outer_angle.to_radians().cos();
+    if cos_theta <= cos_outer {
+      return None;
+    }
+
+    if cos_theta < cos_inner {
+      let x = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+      sample.radiance *= x * x * (3.0 - 2.0 * x);
+    }
+
+    Some(sample)
+  }
+}
+
+// emissive geometry sampled for next event estimation, emitting planes, cylinders and
+// instances are not sampled and only light the scene through rays that happen to hit them
+pub struct AreaLight {
+  pub object: usize, // index of the emitting object in the scene
+  shape     : Emitter,
+}
+
+enum Emitter {
+  Sphere { center: Point3, radius: f64 },
+  // a triangle or the emissive faces of a mesh, picked in proportion to their area
+  Triangles { vertices: Vec<[Point3; 3]>, cdf: Vec<f64>, area: f64 },
+}
+
+impl AreaLight {
+  // None when `object` does not emit light or cannot be sampled
+  pub fn from_object(index: usize, object: &Object) -> Option<Self> {
+    let emissive = |triangle: &&Triangle| triangle.material.is_emissive();
+    let shape = match object {
+      Object::Sphere(s) if s.material.is_emissive() => Emitter::Sphere { center: s.center, radius: s.radius.abs() },
+      Object::Triangle(t) => Emitter::triangles(std::slice::from_ref(t).iter().filter(emissive))?,
+      Object::Mesh(m)     => Emitter::triangles(m.triangles().iter().filter(emissive))?,
+      _ => return None,
+    };
+    Some(AreaLight { object: index, shape })
+  }
+
+  // unit direction from `point` towards a random point of the light, the distance to that
+  // point and its solid angle density, `pick` chooses among the triangles of a mesh
+  pub fn sample(&self, point: Point3, (s, t): (f64, f64), pick: f64) -> Option<(Vec3, f64, f64)> {
+    match &self.shape {
+      Emitter::Sphere { center, radius } => {
+        let to_center = *center - point;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= radius * radius {
+          // inside the sphere every point is visible, sample its whole surface
+          let target = *center + *radius * Vec3::on_unit_sphere((s, t));
+          return to_solid_angle(point, target, (target - *center) / *radius, 1.0 / (4.0 * PI * radius * radius));
+        }
+
+        // uniform over the cone of directions the sphere covers
+        let cos_max = (1.0 - radius * radius / distance_squared).max(0.0).sqrt();
+        let cos_theta = 1.0 - s * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * t;
+        let w = to_center / distance_squared.sqrt();
+        let (u, v) = w.basis();
+        let dir = (sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w).unit();
+        // the sampled point is where `dir` first meets the sphere
+        let b = dir.dot(&to_center);
+        let distance = b - (b * b - distance_squared + radius * radius).max(0.0).sqrt();
+        Some((dir, distance, 1.0 / (2.0 * PI * (1.0 - cos_max))))
+      }
+      Emitter::Triangles { vertices, cdf, area } => {
+        let index = cdf.partition_point(|&c| c <= pick * area).min(vertices.len() - 1);
+        let [a, b, c] = vertices[index];
+        let su = s.sqrt();
+        let target = (1.0 - su) * a + (t * su) * b + ((1.0 - t) * su) * c;
+        to_solid_angle(point, target, (b - a).cross(&(c - a)).unit(), 1.0 / area)
+      }
+    }
+  }
+
+  // density `sample` picks the direction from `point` towards `hit` with, `hit` being
+  // the point of this light seen along that direction
+  pub fn pdf(&self, point: Point3, hit: &HitRecord) -> f64 {
+    match &self.shape {
+      Emitter::Sphere { center, radius } => {
+        let distance_squared = (*center - point).length_squared();
+        if distance_squared <= radius * radius {
+          return to_solid_angle(point, hit.point, hit.normal, 1.0 / (4.0 * PI * radius * radius)).map_or(0.0, |(_, _, pdf)| pdf);
+        }
+        let cos_max = (1.0 - radius * radius / distance_squared).max(0.0).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_max))
+      }
+      Emitter::Triangles { area, .. } => {
+        to_solid_angle(point, hit.point, hit.normal, 1.0 / area).map_or(0.0, |(_, _, pdf)| pdf)
+      }
+    }
+  }
+}
+
+impl Emitter {
+  fn triangles<'a>(triangles: impl Iterator<Item = &'a Triangle>) -> Option<Emitter> {
+    let mut vertices = Vec::new();
+    let mut cdf      = Vec::new();
+    let mut area     = 0.0;
+    for triangle in triangles {
+      let [a, b, c] = triangle.vertices;
+      area += 0.5 * (b - a).cross(&(c - a)).length();
+      vertices.push(triangle.vertices);
+      cdf.push(area);
+    }
+    (area > 0.0).then_some(Emitter::Triangles { vertices, cdf, area })
+  }
+}
+
+// direction and distance from `point` to `target`, and the area density `area_pdf`
+// converted to solid angle
+fn to_solid_angle(point: Point3, target: Point3, normal: Vec3, area_pdf: f64) -> Option<(Vec3, f64, f64)> {
+  let offset = target - point;
+  let distance_squared = offset.length_squared();
+  let distance = distance_squared.sqrt();
+  let dir = offset / distance;
+  let cos_light = normal.dot(&dir).abs();
+  if distance_squared < EPSILON || cos_light < EPSILON {
+    return None;
+  }
+  Some((dir, distance, area_pdf * distance_squared / cos_light))
+}
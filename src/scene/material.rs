@@ -1,61 +1,392 @@
-use crate::{color::Color, math::{Vec3, EPSILON}, ray::Ray};
+use std::f64::consts::PI;
+
+use crate::{color::Color, math::{Vec3, EPSILON}, ray::Ray, sampler::Sampler};
 use crate::scene::HitRecord;
+use crate::scene::texture::SharedTexture;
+
+// a direction picked by a material for the next bounce
+pub struct BsdfSample {
+  pub dir       : Vec3, // unit direction the light arrives from, away from the surface
+  pub throughput: Vec3, // bsdf * cos / pdf, what the radiance along `dir` is scaled by
+  pub pdf       : f64,  // solid angle density of `dir`, the lobe probability for specular samples
+  pub specular  : bool, // picked from a delta or otherwise unevaluable lobe, `eval` and `pdf` do not cover it
+}
 
 pub trait Material {
-  fn albedo(&self) -> Color;
-  fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+  // surface color at the hit, looked up in the material's texture
+  fn albedo(&self, rec: &HitRecord) -> Color;
+
+  // samples the direction of the next bounce, None when the path ends here
+  fn scatter(&self, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+  // bsdf for light arriving from `wi` and leaving towards the ray origin, without the
+  // cosine term, zero for materials that only scatter specularly
+  fn eval(&self, _ray: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Vec3 {
+    Vec3::zero()
+  }
+
+  // density `scatter` picks `wi` with, measured in solid angle
+  fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _wi: &Vec3) -> f64 {
+    0.0
+  }
+
+  // part of a uniform ambient light reflected towards the ray origin, zero for materials
+  // that only scatter specularly
+  fn ambient(&self, _rec: &HitRecord) -> Vec3 {
+    Vec3::zero()
+  }
+
+  // radiance given off by the surface towards the ray origin
+  fn emitted(&self, _ray: &Ray, _rec: &HitRecord) -> Vec3 {
+    Vec3::zero()
+  }
+  // true when `emitted` can be non-zero, such surfaces are sampled as area lights
+  fn is_emissive(&self) -> bool {
+    false
+  }
 }
 
 pub struct Solid {
-  pub albedo: Color,
+  pub albedo: SharedTexture,
 }
 
 pub struct BasicMetal {
-  pub albedo: Color,
+  pub albedo: SharedTexture,
   pub fuzz: f64,
 }
 
+// emits light from the front side of the surface and reflects nothing,
+// any object using it becomes an area light
+pub struct DiffuseLight {
+  pub color    : SharedTexture,
+  pub intensity: f64,
+}
+
+// transparent material such as glass or water, `albedo` tints the transmitted light
+pub struct Dielectric {
+  pub albedo: SharedTexture,
+  pub refraction_index: f64,
+}
+
+// glTF metallic-roughness: a GGX (trowbridge-reitz) specular lobe with schlick fresnel
+// over a lambertian base, `metallic` blends towards a conductor tinted by the base color
+pub struct MetallicRoughness {
+  pub base_color: SharedTexture,
+  pub metallic  : f64, // 0 for dielectrics, 1 for metals
+  pub roughness : f64, // perceptual roughness in [0, 1], the GGX alpha is its square
+  // linear texture scaling the factors as glTF packs it, roughness in green and
+  // metallic in blue
+  pub metallic_roughness: Option<SharedTexture>,
+}
+
+// metallic-roughness parameters looked up at one hit
+struct Surface {
+  base_color: Vec3,
+  metallic  : f64,
+  alpha     : f64,
+}
+
+// reflectance of dielectrics at normal incidence, what glTF assumes (an index of 1.5)
+const DIELECTRIC_F0: f64 = 0.04;
+// narrowest GGX lobe, smoother surfaces are rendered at this roughness to keep the
+// distribution finite
+const MIN_ALPHA: f64 = 1e-3;
+
 impl Material for Solid {
-  fn albedo(&self) -> Color {
-      self.albedo
+  fn albedo(&self, rec: &HitRecord) -> Color {
+    self.albedo.value(rec.uv, rec.point)
+  }
+
+  // cosine weighted, the cosine and 1/pi of the bsdf cancel with the pdf
+  fn scatter(&self, _ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+    let dir = Vec3::cosine_hemisphere(&rec.normal, sampler.get_2d());
+    let pdf = dir.dot(&rec.normal) / PI;
+    if pdf <= 0.0 {
+      return None;
+    }
+
+    Some(BsdfSample { dir, throughput: Vec3::from(self.albedo(rec)), pdf, specular: false })
   }
 
-  fn scatter(&self, _ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-    let mut scatter_dir = rec.normal + Vec3::random_unit_sphere();
-    
-    if scatter_dir.dot(&rec.normal) < 0.0 {
-      scatter_dir = -scatter_dir;
+  fn eval(&self, _ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Vec3 {
+    if wi.dot(&rec.normal) <= 0.0 {
+      return Vec3::zero();
     }
-    
-    let offset_origin = rec.point + rec.normal * 1e-4;
-    Some( (Ray::new(offset_origin, scatter_dir.unit()), self.albedo()) )
-    
+    Vec3::from(self.albedo(rec)) / PI
+  }
+
+  fn pdf(&self, _ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+    wi.dot(&rec.normal).max(0.0) / PI
+  }
+
+  // the bsdf integrated over the hemisphere against the cosine
+  fn ambient(&self, rec: &HitRecord) -> Vec3 {
+    Vec3::from(self.albedo(rec))
   }
 }
 
-impl Material for BasicMetal {
-  fn albedo(&self) -> Color {
-      self.albedo
+impl Material for MetallicRoughness {
+  fn albedo(&self, rec: &HitRecord) -> Color {
+    self.base_color.value(rec.uv, rec.point)
   }
 
-  fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-    let reflected_dir = ray.dir - 2.0 * ray.dir.dot(&rec.normal) * rec.normal;    
-    
-    if reflected_dir.dot(&rec.normal) < EPSILON {
+  // picks the specular lobe through the distribution of visible normals or the diffuse one
+  // by cosine, the pdf covers both so light samples can be weighted against either
+  fn scatter(&self, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+    let frame = Frame::new(rec.normal);
+    let wo = frame.to_local(-ray.dir.unit());
+    let (choice, u) = (sampler.get_1d(), sampler.get_2d());
+    if wo.z <= 0.0 {
+      return None;
+    }
+
+    let surface = self.surface(rec);
+    let wi = if choice < surface.specular_probability() {
+      let h = sample_visible_normal(wo, surface.alpha, u);
+      2.0 * wo.dot(&h) * h - wo
+    } else {
+      Vec3::cosine_hemisphere(&Vec3::new(0.0, 0.0, 1.0), u)
+    };
+
+    let pdf = surface.pdf_local(wo, wi);
+    if wi.z <= 0.0 || pdf <= 0.0 {
       return None;
     }
+    let throughput = surface.eval_local(wo, wi) * (wi.z / pdf);
+    Some(BsdfSample { dir: frame.to_world(wi), throughput, pdf, specular: false })
+  }
+
+  fn eval(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> Vec3 {
+    let frame = Frame::new(rec.normal);
+    self.surface(rec).eval_local(frame.to_local(-ray.dir.unit()), frame.to_local(*wi))
+  }
+
+  fn pdf(&self, ray: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+    let frame = Frame::new(rec.normal);
+    self.surface(rec).pdf_local(frame.to_local(-ray.dir.unit()), frame.to_local(*wi))
+  }
 
-    let mut random_offset = Vec3::random_unit_sphere();
+  // what both lobes reflect at normal incidence, close to their integral over the
+  // hemisphere without the sharp peak that evaluating a smooth lobe along the normal gives
+  fn ambient(&self, rec: &HitRecord) -> Vec3 {
+    let surface = self.surface(rec);
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let dielectric = surface.base_color * (1.0 - DIELECTRIC_F0) + white * DIELECTRIC_F0;
+    dielectric * (1.0 - surface.metallic) + surface.base_color * surface.metallic
+  }
+}
+
+impl MetallicRoughness {
+  fn surface(&self, rec: &HitRecord) -> Surface {
+    let (mut metallic, mut roughness) = (self.metallic, self.roughness);
+    if let Some(texture) = &self.metallic_roughness {
+      let texel = Vec3::from(texture.value(rec.uv, rec.point));
+      metallic  *= texel.z;
+      roughness *= texel.y;
+    }
+    Surface {
+      base_color: Vec3::from(self.albedo(rec)),
+      metallic,
+      alpha     : (roughness * roughness).max(MIN_ALPHA),
+    }
+  }
+}
+
+impl Surface {
+  // metals have no diffuse lobe, dielectrics split their samples evenly
+  fn specular_probability(&self) -> f64 {
+    0.5 + 0.5 * self.metallic
+  }
+
+  // glTF's bsdf with a height-correlated smith visibility, directions in the shading frame
+  fn eval_local(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return Vec3::zero();
+    }
+    let (alpha, base_color) = (self.alpha, self.base_color);
+    let h = (wo + wi).unit();
+    let schlick = (1.0 - wo.dot(&h).max(0.0)).powi(5);
+    let specular = ggx_distribution(h.z, alpha) * smith_masking(wo, wi, alpha) / (4.0 * wo.z * wi.z);
+
+    let fresnel_dielectric = DIELECTRIC_F0 + (1.0 - DIELECTRIC_F0) * schlick;
+    let fresnel_metal = base_color + (Vec3::new(1.0, 1.0, 1.0) - base_color) * schlick;
+    let dielectric = base_color * ((1.0 - fresnel_dielectric) / PI) + Vec3::new(1.0, 1.0, 1.0) * (specular * fresnel_dielectric);
+    let metal = fresnel_metal * specular;
+    dielectric * (1.0 - self.metallic) + metal * self.metallic
+  }
+
+  fn pdf_local(&self, wo: Vec3, wi: Vec3) -> f64 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+      return 0.0;
+    }
+    let alpha = self.alpha;
+    let h = (wo + wi).unit();
+    // visible normal density over the jacobian of the reflection, 4 (wo . h)
+    let specular = ggx_distribution(h.z, alpha) * smith_g1(wo, alpha) / (4.0 * wo.z);
+    let diffuse  = wi.z / PI;
+    let p = self.specular_probability();
+    p * specular + (1.0 - p) * diffuse
+  }
+}
+
+impl Material for BasicMetal {
+  fn albedo(&self, rec: &HitRecord) -> Color {
+    self.albedo.value(rec.uv, rec.point)
+  }
+
+  // the fuzzed mirror has no density we can evaluate, so it counts as specular
+  fn scatter(&self, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+    let reflected_dir = ray.dir.unit().reflect(&rec.normal);
+
+    let mut random_offset = Vec3::on_unit_sphere(sampler.get_2d());
     if random_offset.dot(&rec.normal) < 0.0 {
-      random_offset= -random_offset;
+      random_offset = -random_offset;
     }
 
     let fuzzed = reflected_dir + (self.fuzz * random_offset);
-    if fuzzed.length_squared() < EPSILON * EPSILON {
+    if fuzzed.length_squared() < EPSILON * EPSILON || fuzzed.dot(&rec.normal) < EPSILON {
       return None;
     }
 
-    let offset_origin = rec.point + rec.normal * EPSILON;
-    Some( (Ray::new(offset_origin, fuzzed.unit()), self.albedo()) )
+    Some(BsdfSample { dir: fuzzed.unit(), throughput: Vec3::from(self.albedo(rec)), pdf: 1.0, specular: true })
+  }
+}
+
+impl Material for Dielectric {
+  fn albedo(&self, rec: &HitRecord) -> Color {
+    self.albedo.value(rec.uv, rec.point)
+  }
+
+  fn scatter(&self, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+    // the normal faces the ray, so leaving the object swaps the indices
+    let eta = if rec.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+
+    let unit_dir  = ray.dir.unit();
+    let cos_theta = (-unit_dir).dot(&rec.normal).min(1.0);
+    let reflectance = fresnel_dielectric(cos_theta, eta);
+
+    // pick reflection or refraction with the fresnel probability, reflectance is 1 on total internal reflection,
+    // the fresnel factor cancels with the probability of the pick
+    if sampler.get_1d() < reflectance {
+      Some(BsdfSample {
+        dir       : unit_dir.reflect(&rec.normal),
+        throughput: Vec3::new(1.0, 1.0, 1.0),
+        pdf       : reflectance,
+        specular  : true,
+      })
+    } else {
+      Some(BsdfSample {
+        dir       : unit_dir.refract(&rec.normal, eta).unit(),
+        throughput: Vec3::from(self.albedo(rec)),
+        pdf       : 1.0 - reflectance,
+        specular  : true,
+      })
+    }
+  }
+}
+
+impl Material for DiffuseLight {
+  fn albedo(&self, rec: &HitRecord) -> Color {
+    self.color.value(rec.uv, rec.point)
+  }
+
+  fn scatter(&self, _ray: &Ray, _rec: &HitRecord, _sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+    None
+  }
+
+  fn emitted(&self, _ray: &Ray, rec: &HitRecord) -> Vec3 {
+    if !rec.front_face {
+      return Vec3::zero();
+    }
+    Vec3::from(self.albedo(rec)) * self.intensity
+  }
+
+  fn is_emissive(&self) -> bool {
+    self.intensity > 0.0
   }
 }
+
+// exact fresnel reflectance for unpolarized light, `eta` is incident over transmitted index
+fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+  let sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+  if sin_t2 >= 1.0 {
+    return 1.0;
+  }
+
+  let cos_t = (1.0 - sin_t2).sqrt();
+  let r_parallel      = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// orthonormal frame with the shading normal as z
+struct Frame {
+  tangent  : Vec3,
+  bitangent: Vec3,
+  normal   : Vec3,
+}
+
+impl Frame {
+  fn new(normal: Vec3) -> Self {
+    let (tangent, bitangent) = normal.basis();
+    Frame { tangent, bitangent, normal }
+  }
+
+  fn to_local(&self, v: Vec3) -> Vec3 {
+    Vec3::new(v.dot(&self.tangent), v.dot(&self.bitangent), v.dot(&self.normal))
+  }
+
+  fn to_world(&self, v: Vec3) -> Vec3 {
+    v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+  }
+}
+
+// trowbridge-reitz density of microfacet normals at `cos_theta` from the surface normal
+fn ggx_distribution(cos_theta: f64, alpha: f64) -> f64 {
+  let a2 = alpha * alpha;
+  let d = cos_theta * cos_theta * (a2 - 1.0) + 1.0;
+  a2 / (PI * d * d)
+}
+
+// smith's lambda for GGX, the ratio of hidden to visible microfacet area along `w`
+fn smith_lambda(w: Vec3, alpha: f64) -> f64 {
+  let cos2 = w.z * w.z;
+  if cos2 <= 0.0 {
+    return f64::INFINITY;
+  }
+  let tan2 = (1.0 - cos2) / cos2;
+  0.5 * (-1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+fn smith_g1(w: Vec3, alpha: f64) -> f64 {
+  1.0 / (1.0 + smith_lambda(w, alpha))
+}
+
+// height-correlated masking and shadowing
+fn smith_masking(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+  1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+// heitz's sampling of the GGX normals visible from `wo`, in the shading frame
+fn sample_visible_normal(wo: Vec3, alpha: f64, (s, t): (f64, f64)) -> Vec3 {
+  // stretch the view direction to the hemisphere configuration
+  let v = Vec3::new(alpha * wo.x, alpha * wo.y, wo.z).unit();
+  let length_squared = v.x * v.x + v.y * v.y;
+  let t1 = if length_squared > 0.0 {
+    Vec3::new(-v.y, v.x, 0.0) / length_squared.sqrt()
+  } else {
+    Vec3::new(1.0, 0.0, 0.0)
+  };
+  let t2 = v.cross(&t1);
+
+  // uniform disk, squashed over the part of it the view direction can see
+  let r = s.sqrt();
+  let phi = 2.0 * PI * t;
+  let p1 = r * phi.cos();
+  let blend = 0.5 * (1.0 + v.z);
+  let p2 = (1.0 - blend) * (1.0 - p1 * p1).max(0.0).sqrt() + blend * r * phi.sin();
+  let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+
+  // back to the ellipsoid configuration
+  Vec3::new(alpha * n.x, alpha * n.y, n.z.max(0.0)).unit()
+}
@@ -1,15 +1,92 @@
-use crate::framebuffer::FrameBuffer;
-use crate::utils::random_double_in;
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ mpsc, Mutex, OnceLock };
+use std::thread;
+
+use crate::aov::{ Aov, AovBuffer, PixelAovs };
+use crate::framebuffer::{ RadianceBuffer, Tile };
+use crate::tonemap::luminance;
 use crate::Color;
 use crate::Camera;
 use crate::Ray;
-use crate::scene::object::{ Object, HitRecord, Hittable };
-use crate::math::Vec3;
+use crate::scene::bvh::{ Bvh, BvhStats, TraversalCounts };
+use crate::scene::environment::Environment;
+use crate::scene::object::{ Object, HitRecord };
+use crate::math::{ Point3, Vec3 };
+use crate::scene::integrator::{ Integrator, PathTracer };
+use crate::scene::light::{ AreaLight, Light };
 
+pub mod bvh;
+pub mod environment;
+pub mod gltf;
+pub mod integrator;
+pub mod json;
 pub mod light;
+pub mod obj;
 pub mod object;
-//pub mod parser;
+pub mod parser;
 pub mod material;
+pub mod noise;
+pub mod texture;
+
+// reads a `.rt` scene, or a glTF one when the extension is `.gltf` or `.glb`
+pub fn load_file(path: &Path, image_width: usize, aspect_ratio: f64) -> Result<Scene, Box<dyn Error>> {
+  let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+  match extension.as_str() {
+    "gltf" | "glb" => Ok(gltf::load_gltf(path, image_width, aspect_ratio)?),
+    _ => Ok(parser::parse_file(path, image_width, aspect_ratio)?),
+  }
+}
+
+// offset applied to the origin of rays leaving a surface to avoid self-intersection
+const RAY_BIAS: f64 = 1e-4;
+
+// running totals of one pixel over every sample taken so far
+#[derive(Clone, Copy, Default)]
+struct PixelEstimate {
+  radiance: Vec3,
+  samples : usize,
+  // running mean and squared deviation of the sample luminance (welford)
+  mean    : f64,
+  m2      : f64,
+  // normal and albedo are summed until the estimate is resolved
+  surface : PixelAovs,
+}
+
+impl PixelEstimate {
+  fn add(&mut self, color: Vec3) {
+    self.radiance += color;
+    self.samples += 1;
+    let value = luminance(color);
+    let delta = value - self.mean;
+    self.mean += delta / self.samples as f64;
+    self.m2 += delta * (value - self.mean);
+  }
+
+  fn variance(&self) -> f64 {
+    self.m2 / self.samples.saturating_sub(1).max(1) as f64
+  }
+
+  // averaged radiance and surface passes
+  fn resolve(&self) -> (Vec3, PixelAovs) {
+    let scale = 1.0 / self.samples.max(1) as f64;
+    let mut surface = self.surface;
+    surface.normal *= scale;
+    surface.albedo *= scale;
+    surface.samples = self.samples;
+    (self.radiance * scale, surface)
+  }
+}
+
+// what every pixel is doing during one round of sampling
+struct Round {
+  first_sample: usize,
+  samples     : usize,
+  surface     : bool, // trace the extra camera ray cast for the surface passes
+  active      : Vec<bool>,
+  estimates   : Vec<PixelEstimate>,
+}
 
 pub struct AmbientLight {
   pub ratio: f64,
@@ -21,7 +98,17 @@ pub struct Scene {
   pub ambient : AmbientLight,
   
   pub objects : Vec<Object>,
-  //pub lights: Vec<Light>,
+  pub lights  : Vec<Light>,
+  // light seen by rays that escape the scene
+  pub environment: Environment,
+  pub integrator : Box<dyn Integrator + Send + Sync>,
+
+  // built on the first cast, dropped whenever objects are added or removed
+  bvh: OnceLock<Bvh>,
+  // emissive objects sampled as lights, sorted by object index, built and dropped like the bvh
+  area_lights: OnceLock<Vec<AreaLight>>,
+  // bvh traversals of the last frame, merged once per tile
+  traversals : Mutex<TraversalCounts>,
 }
 
 impl Scene {
@@ -30,72 +117,326 @@ impl Scene {
       camera,
       ambient,
       objects: Vec::new(),
-      //lights: Vec::new(),
+      lights: Vec::new(),
+      environment: Environment::default(),
+      integrator: Box::new(PathTracer::default()),
+      bvh: OnceLock::new(),
+      area_lights: OnceLock::new(),
+      traversals: Mutex::new(TraversalCounts::default()),
     }
   }
 
   pub fn add_object(&mut self, object: Object) {
     self.objects.push(object);  
+    self.bvh.take();
+    self.area_lights.take();
   }
 
-  pub fn clear(&mut self) {
-    self.objects.clear();
-    //self.lights.clear();
+  pub fn add_light(&mut self, light: Light) {
+    self.lights.push(light);
   }
 
-  pub fn render_frame(&self) -> FrameBuffer {
-    let mut buffer = FrameBuffer::new(self.camera.image_width, self.camera.image_height);
-    for j in 0..self.camera.image_height {
-      for i in 0..self.camera.image_width {
-        // anti aliasing
-        let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
+  pub fn bvh(&self) -> &Bvh {
+    self.bvh.get_or_init(|| Bvh::build(&self.objects))
+  }
 
-        for _sample in 0..self.camera.sampling_rate {
-          let pixel = self.camera.viewport.p00
-                    + (i as f64 + random_double_in(-0.5, 0.5)) * self.camera.viewport.pdu
-                    + (j as f64 + random_double_in(-0.5, 0.5)) * self.camera.viewport.pdv;
-          let ray = Ray::new(self.camera.position, (pixel - self.camera.position).unit());
-          pixel_color += self.ray_color(&ray, self.camera.max_depth);
-        }
+  pub fn area_lights(&self) -> &[AreaLight] {
+    self.area_lights.get_or_init(|| {
+      self.objects
+        .iter()
+        .enumerate()
+        .filter_map(|(index, object)| AreaLight::from_object(index, object))
+        .collect()
+    })
+  }
+
+  // the area light made from object `object`, if it is one
+  pub fn area_light(&self, object: usize) -> Option<&AreaLight> {
+    let lights = self.area_lights();
+    lights.binary_search_by_key(&object, |light| light.object).ok().map(|i| &lights[i])
+  }
 
-        buffer[(i, j)]= Color::Rgb(pixel_color / (self.camera.sampling_rate as f64))
-                        .gamma_correct(2.0)
-                        .to_rgb_bytes();
+  // tree shape and the ray/box tests counted since the start of the last frame
+  pub fn bvh_stats(&self) -> BvhStats {
+    self.bvh().stats(*self.traversals.lock().unwrap())
+  }
+
+  // linear radiance of every pixel, averaged over `sampling_rate` samples or as many as
+  // adaptive sampling takes,
+  // `first_sample` numbers the samples so successive calls draw new ones
+  pub fn render(&self, first_sample: usize) -> RadianceBuffer {
+    self.render_with_aovs(&[], first_sample).0
+  }
+
+  // radiance plus one buffer per requested pass, in the order of `aovs`,
+  // with adaptive sampling every round of `sampling_rate` samples only revisits
+  // the pixels that are still noisy, or next to one that is
+  pub fn render_with_aovs(&self, aovs: &[Aov], first_sample: usize) -> (RadianceBuffer, Vec<AovBuffer>) {
+    let (width, height) = (self.camera.image_width, self.camera.image_height);
+    let tiles = Tile::split(width, height, self.camera.tile_size);
+    // drop what this thread cast outside of rendering
+    bvh::take_counts();
+    *self.traversals.lock().unwrap() = TraversalCounts::default();
+
+    let spp = self.camera.sampling_rate;
+    let max_samples = self.camera.adaptive.map_or(spp, |adaptive| adaptive.max_samples.max(spp));
+
+    let mut round = Round {
+      first_sample,
+      samples  : spp,
+      // the sample count is always known, the other passes need an extra cast per sample
+      surface  : aovs.iter().any(|&aov| aov != Aov::Samples),
+      active   : vec![true; width * height],
+      estimates: vec![PixelEstimate::default(); width * height],
+    };
+
+    let mut taken = 0;
+    while taken < max_samples && round.active.contains(&true) {
+      round.first_sample = first_sample + taken;
+      round.samples      = spp.min(max_samples - taken);
+      round.estimates    = self.render_round(&tiles, &round);
+      taken += round.samples;
+
+      if let Some(adaptive) = self.camera.adaptive {
+        let noisy: Vec<bool> = round.estimates.iter().map(|e| !adaptive.converged(e.mean, e.variance(), e.samples)).collect();
+        round.active = spread(&noisy, width, height)
+          .into_iter()
+          .zip(&round.active)
+          .map(|(noisy, &active)| noisy && active)
+          .collect();
+      }
+    }
+
+    let mut image  = RadianceBuffer::new(width, height);
+    let mut passes: Vec<AovBuffer> = aovs.iter().map(|&aov| AovBuffer::new(aov, width, height)).collect();
+    for (index, estimate) in round.estimates.iter().enumerate() {
+      let (color, surface) = estimate.resolve();
+      image.pixels[index] = [color.x as f32, color.y as f32, color.z as f32];
+      for pass in &mut passes {
+        pass.pixels[index] = surface.value(pass.aov);
       }
     }
-    buffer
+    (image, passes)
   }
-  
-  fn cast(&self, ray: &Ray) -> Option<HitRecord> {
-    let mut closest   : Option<HitRecord> = None;
-    let mut closest_t = f64::INFINITY;
-    
-    for object in &self.objects {
-      if let Some(hit) = object.hit(ray) {
-        if hit.t < closest_t {
-          closest_t = hit.t;
-          closest = Some(hit);
+
+  // one round of samples over the active pixels, returns the updated estimates
+  fn render_round(&self, tiles: &[Tile], round: &Round) -> Vec<PixelEstimate> {
+    let width   = self.camera.image_width;
+    let mut estimates = round.estimates.clone();
+    let threads = self.camera.thread_count().min(tiles.len());
+
+    if threads <= 1 {
+      for tile in tiles {
+        tile.write(&mut estimates, width, &self.render_tile(tile, round));
+      }
+      return estimates;
+    }
+
+    // workers pull the next tile off a shared counter and send back its pixels
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|s| {
+      for _ in 0..threads {
+        let sender = sender.clone();
+        let next   = &next;
+        s.spawn(move || {
+          while let Some(tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+            if sender.send((*tile, self.render_tile(tile, round))).is_err() {
+              break;
+            }
+          }
+        });
+      }
+      drop(sender);
+
+      for (tile, pixels) in receiver {
+        tile.write(&mut estimates, width, &pixels);
+      }
+    });
+
+    estimates
+  }
+
+  // renders the pixels of a tile in row-major order, inactive ones are passed through
+  fn render_tile(&self, tile: &Tile, round: &Round) -> Vec<PixelEstimate> {
+    let width = self.camera.image_width;
+    let mut pixels = Vec::with_capacity(tile.width * tile.height);
+    for j in tile.y..tile.y + tile.height {
+      for i in tile.x..tile.x + tile.width {
+        let mut estimate = round.estimates[j * width + i];
+        if round.active[j * width + i] {
+          self.render_pixel(i, j, round, &mut estimate);
         }
+        pixels.push(estimate);
       }
     }
+    self.traversals.lock().unwrap().add(bvh::take_counts());
+    pixels
+  }
+
+  // adds the samples of this round to the pixel, and what its camera rays hit first
+  // when surface passes were requested
+  fn render_pixel(&self, i: usize, j: usize, round: &Round, estimate: &mut PixelEstimate) {
+    let mut sampler = self.camera.sampler.create(self.camera.seed, i, j, self.camera.sampling_rate);
+
+    // anti aliasing
+    for sample in round.first_sample..round.first_sample + round.samples {
+      sampler.start_sample(sample);
+      let (dx, dy) = sampler.get_2d();
+      let lens     = sampler.get_2d();
+      let ray = self.camera.ray(i, j, (dx - 0.5, dy - 0.5), lens);
+      let color = self.integrator.radiance(self, &ray, sampler.as_mut());
+
+      if round.surface && let Some((object, hit)) = self.bvh().cast(&self.objects, &ray) {
+        let surface = &mut estimate.surface;
+        if surface.object.is_none() {
+          surface.depth  = hit.t;
+          surface.object = Some(object);
+        }
+        surface.normal += if hit.front_face { hit.normal } else { -hit.normal };
+        surface.albedo += Vec3::from(hit.material.albedo(&hit));
+      }
+      estimate.add(color);
+    }
+  }
+
+  fn cast(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+    self.bvh().cast(&self.objects, ray).map(|(_, hit)| hit)
+  }
 
-    closest
+  // ambient light reflected by `hit`, a rough stand-in for indirect light
+  fn ambient_light(&self, hit: &HitRecord) -> Vec3 {
+    hit.material.ambient(hit) * Vec3::from(self.ambient.color) * self.ambient.ratio
   }
 
-  fn ray_color(&self, ray: &Ray, depth: u32) -> Vec3 {
-    if depth == 0 {
-      return Vec3::zero();
+  // light reaching `hit` straight from the point, directional and spot lights, area
+  // lights, the ambient term and the indirect part are left to the integrator
+  fn direct_light(&self, ray: &Ray, hit: &HitRecord) -> Vec3 {
+    let normal = hit.normal;
+    let mut total = Vec3::zero();
+
+    for light in &self.lights {
+      let Some(sample) = light.sample(hit.point) else {
+        continue;
+      };
+
+      let cos_theta = normal.dot(&sample.dir);
+      if cos_theta <= 0.0 {
+        continue;
+      }
+
+      let bsdf = hit.material.eval(ray, hit, &sample.dir);
+      if bsdf == Vec3::zero() {
+        continue;
+      }
+
+      let origin = hit.point + normal * RAY_BIAS;
+      if self.occluded(origin, sample.dir, sample.distance - RAY_BIAS) {
+        continue;
+      }
+
+      total += bsdf * sample.radiance * cos_theta;
     }
 
-    if let Some(hit) = self.cast(ray) {
-      match hit.material.scatter(ray, &hit) {
-        Some((scattered_ray, albedo)) => return Vec3::from(albedo) * self.ray_color(&scattered_ray, depth - 1),
-        None => return Vec3::zero()
+    total
+  }
+
+  // true when something blocks the segment from `origin` along `dir` up to `max_t`
+  fn occluded(&self, origin: Point3, dir: Vec3, max_t: f64) -> bool {
+    self.cast(&Ray::new(origin, dir)).is_some_and(|hit| hit.t < max_t)
+  }
+}
+
+// origin of a ray leaving `hit` along `dir`, pushed off the surface on the side `dir`
+// points to so reflected and transmitted rays both clear it
+fn offset_origin(hit: &HitRecord, dir: &Vec3) -> Point3 {
+  hit.point + hit.normal * RAY_BIAS.copysign(dir.dot(&hit.normal))
+}
+
+// marks every pixel with a marked pixel in its 3x3 neighbourhood, a lone noisy pixel
+// often means its neighbours just have not seen the rare bright paths yet
+fn spread(mask: &[bool], width: usize, height: usize) -> Vec<bool> {
+  let mut spread = vec![false; mask.len()];
+  for y in 0..height {
+    for x in 0..width {
+      if !mask[y * width + x] {
+        continue;
       }
+      for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+          spread[ny * width + nx] = true;
+        }
+      }
+    }
+  }
+  spread
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+  use crate::sampler::SamplerKind;
+  use crate::scene::material::Solid;
+  use crate::scene::object::Sphere;
+  use crate::scene::texture::constant;
+
+  // a sphere filling part of a small image, so many pixels straddle its edge
+  fn sphere_scene() -> Scene {
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 4.0), Vec3::new(0.0, 0.0, -1.0), 40.0, 1.0, 16);
+    camera.sampling_rate = 8;
+    camera.seed = 5;
+    let mut scene = Scene::new(camera, AmbientLight { ratio: 0.2, color: Color::rgb(1.0, 1.0, 1.0) });
+    scene.add_object(Object::Sphere(Sphere {
+      center  : Point3::new(0.4, 0.3, 0.0),
+      radius  : 1.0,
+      material: Arc::new(Solid { albedo: constant(Color::rgb(0.8, 0.5, 0.2)) }),
+    }));
+    scene
+  }
+
+  // every bit of the image and its passes, rendered over `threads` threads
+  fn render_bits(sampler: SamplerKind, threads: usize, seed: u64) -> Vec<u32> {
+    let mut scene = sphere_scene();
+    scene.camera.sampler   = sampler;
+    scene.camera.threads   = threads;
+    scene.camera.tile_size = 4;
+    scene.camera.seed      = seed;
+    let (image, passes) = scene.render_with_aovs(&[Aov::Depth, Aov::Normal, Aov::Albedo], 0);
+    image.pixels.iter().chain(passes.iter().flat_map(|pass| &pass.pixels)).flatten().map(|v| v.to_bits()).collect()
+  }
+
+  #[test]
+  fn renders_repeat_across_thread_counts() {
+    let serial = render_bits(SamplerKind::Independent, 1, 5);
+    assert_eq!(render_bits(SamplerKind::Independent, 4, 5), serial);
+    assert_eq!(render_bits(SamplerKind::Independent, 3, 5), serial);
+    assert_ne!(render_bits(SamplerKind::Independent, 1, 6), serial);
+  }
+
+  #[test]
+  fn every_sampler_repeats_across_thread_counts() {
+    for sampler in [SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+      assert_eq!(render_bits(sampler, 4, 5), render_bits(sampler, 1, 5), "{sampler:?}");
     }
-    let unit_direction = ray.dir.unit();
-    let a = 0.5 * (unit_direction.y + 1.0);
-    (1.0 - a) * Vec3::new(1.0, 1.0, 1.0) + a * Vec3::new(0.3, 0.5, 1.0)
   }
 
+  #[test]
+  fn surface_passes_agree_on_edges() {
+    let scene = sphere_scene();
+    let (_, passes) = scene.render_with_aovs(&[Aov::Depth, Aov::Normal, Aov::ObjectId], 0);
+    let [depth, normal, id] = &passes[..] else { unreachable!() };
+
+    let mut edges = 0;
+    for ((depth, normal), id) in depth.pixels.iter().zip(&normal.pixels).zip(&id.pixels) {
+      let hit = normal != &[0.0; 3];
+      assert_eq!(depth[0].is_finite(), hit);
+      assert_eq!(id[0], if hit { 0.0 } else { -1.0 });
+      // partly covered pixels average a shorter normal
+      edges += usize::from(hit && normal.iter().map(|c| c * c).sum::<f32>() < 0.9);
+    }
+    assert!(edges > 0);
+  }
 }
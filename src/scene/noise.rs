@@ -0,0 +1,113 @@
+// 3D noise functions behind the procedural textures
+//
+// the lattice hashes are fixed, so a scene looks the same on every render
+// whatever `--seed` is
+
+use crate::math::{ Point3, Vec3 };
+use crate::utils::{ mix, Rng };
+
+// ken perlin's improved gradient noise
+pub struct Perlin {
+  // permutation of 0..256 repeated twice so lookups never wrap
+  permutation: [u8; 512],
+}
+
+impl Perlin {
+  pub fn new(seed: u64) -> Self {
+    let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut rng = Rng::new(seed, 0);
+    for i in (1..256).rev() {
+      let j = rng.next_u32() as usize % (i + 1);
+      table.swap(i, j);
+    }
+    Perlin { permutation: std::array::from_fn(|i| table[i % 256]) }
+  }
+
+  // smooth noise in [-1, 1], zero on every lattice point
+  pub fn noise(&self, p: Point3) -> f64 {
+    let (xf, yf, zf) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - xf, p.y - yf, p.z - zf);
+    let (i, j, k) = ((xf as i64 & 255) as usize, (yf as i64 & 255) as usize, (zf as i64 & 255) as usize);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let perm = &self.permutation;
+    let a  = perm[i] as usize + j;
+    let aa = perm[a] as usize + k;
+    let ab = perm[a + 1] as usize + k;
+    let b  = perm[i + 1] as usize + j;
+    let ba = perm[b] as usize + k;
+    let bb = perm[b + 1] as usize + k;
+
+    lerp(w,
+      lerp(v,
+        lerp(u, gradient(perm[aa], x, y, z), gradient(perm[ba], x - 1.0, y, z)),
+        lerp(u, gradient(perm[ab], x, y - 1.0, z), gradient(perm[bb], x - 1.0, y - 1.0, z)),
+      ),
+      lerp(v,
+        lerp(u, gradient(perm[aa + 1], x, y, z - 1.0), gradient(perm[ba + 1], x - 1.0, y, z - 1.0)),
+        lerp(u, gradient(perm[ab + 1], x, y - 1.0, z - 1.0), gradient(perm[bb + 1], x - 1.0, y - 1.0, z - 1.0)),
+      ),
+    )
+  }
+
+  // sum of `octaves` absolute noise layers, each at twice the frequency and half the weight
+  // of the previous one, roughly in [0, 1]
+  pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut point = p;
+    let mut weight = 1.0;
+    for _ in 0..octaves {
+      sum += weight * self.noise(point).abs();
+      weight *= 0.5;
+      point *= 2.0;
+    }
+    sum
+  }
+}
+
+// distances to the closest and second closest feature point, with one point
+// hashed into every unit cell (steven worley's cellular noise)
+pub fn worley(p: Point3) -> (f64, f64) {
+  let cell = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+  let mut closest = (f64::INFINITY, f64::INFINITY);
+
+  for dz in -1..=1 {
+    for dy in -1..=1 {
+      for dx in -1..=1 {
+        let (x, y, z) = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+        let hash = mix((x as u64).wrapping_mul(0x8da6b343) ^ (y as u64).wrapping_mul(0xd8163841) ^ (z as u64).wrapping_mul(0xcb1ab31f));
+        let offset = Vec3::new(
+          (hash & 0x1fffff) as f64 / (1u64 << 21) as f64,
+          ((hash >> 21) & 0x1fffff) as f64 / (1u64 << 21) as f64,
+          ((hash >> 42) & 0x1fffff) as f64 / (1u64 << 21) as f64,
+        );
+        let feature = Point3::new(x as f64, y as f64, z as f64) + offset;
+        let distance = (feature - p).length();
+
+        if distance < closest.0 {
+          closest = (distance, closest.0);
+        } else if distance < closest.1 {
+          closest.1 = distance;
+        }
+      }
+    }
+  }
+  closest
+}
+
+// 6t^5 - 15t^4 + 10t^3, flat first and second derivatives at the lattice points
+fn fade(t: f64) -> f64 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+  a + t * (b - a)
+}
+
+// dot product with one of the 12 cube edge directions picked by the hash
+fn gradient(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+  let h = hash & 15;
+  let u = if h < 8 { x } else { y };
+  let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+  (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
@@ -0,0 +1,303 @@
+// loader for Wavefront `.obj` meshes and their `.mtl` material libraries
+//
+// supported statements:
+//   obj: v, vt, vn, f (any vertex/uv/normal combination, negative indices,
+//        polygons are fan triangulated), mtllib, usemtl
+//   mtl: newmtl, Kd, Ks, Ke, Ns, Ni, d, Tr, Tf, illum, map_Kd (png or jpeg, replaces Kd)
+//
+// everything else (groups, smoothing, free-form geometry, other texture maps) is ignored
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::math::{ Point3, Vec3 };
+use crate::scene::material::{ BasicMetal, Dielectric, DiffuseLight, Material, Solid };
+use crate::scene::object::{ Mesh, Triangle };
+use crate::scene::texture::{ constant, ImageTexture, SharedTexture, WrapMode };
+
+type SharedMaterial = Arc<dyn Material + Send + Sync>;
+
+#[derive(Debug)]
+pub struct ObjError {
+  pub file   : PathBuf,
+  pub line   : usize, // 1-based, 0 when the error is not tied to a line
+  pub message: String,
+}
+
+impl fmt::Display for ObjError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.line == 0 {
+      write!(f, "{}: {}", self.file.display(), self.message)
+    } else {
+      write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+    }
+  }
+}
+
+impl std::error::Error for ObjError {}
+
+// placement applied to every vertex while loading
+pub struct ObjPlacement {
+  pub offset: Vec3,
+  pub scale : f64,
+}
+
+// loads `path` into a mesh, faces without a material use `default_material`
+pub fn load_obj(path: &Path, placement: &ObjPlacement, default_material: SharedMaterial) -> Result<Mesh, ObjError> {
+  let source = read(path)?;
+  let error = |line: usize, message: String| ObjError { file: path.to_path_buf(), line, message };
+
+  let mut positions: Vec<Point3>     = Vec::new();
+  let mut normals  : Vec<Vec3>       = Vec::new();
+  let mut uvs      : Vec<(f64, f64)> = Vec::new();
+  let mut triangles: Vec<Triangle>   = Vec::new();
+
+  let mut library : HashMap<String, SharedMaterial> = HashMap::new();
+  let mut material: SharedMaterial = Arc::clone(&default_material);
+
+  for (index, raw) in source.lines().enumerate() {
+    let line = index + 1;
+    let text = raw.split('#').next().unwrap_or("");
+    let mut tokens = text.split_whitespace();
+
+    let Some(keyword) = tokens.next() else {
+      continue;
+    };
+    let args: Vec<&str> = tokens.collect();
+
+    match keyword {
+      "v" => {
+        let [x, y, z] = numbers::<3>(&args).map_err(|m| error(line, m))?;
+        positions.push(placement.offset + placement.scale * Point3::new(x, y, z));
+      }
+      "vn" => {
+        let [x, y, z] = numbers::<3>(&args).map_err(|m| error(line, m))?;
+        normals.push(Vec3::new(x, y, z).unit());
+      }
+      "vt" => {
+        // the optional third coordinate is only used by 3D textures
+        let [u, v] = numbers::<2>(&args[..args.len().min(2)]).map_err(|m| error(line, m))?;
+        uvs.push((u, v));
+      }
+      "f" => {
+        if args.len() < 3 {
+          return Err(error(line, format!("face needs at least 3 vertices, found {}", args.len())));
+        }
+
+        let mut corners = Vec::with_capacity(args.len());
+        for arg in &args {
+          corners.push(
+            face_vertex(arg, positions.len(), uvs.len(), normals.len()).map_err(|m| error(line, m))?
+          );
+        }
+
+        for i in 1..corners.len() - 1 {
+          let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+          let normals = match (a.1, b.1, c.1) {
+            (Some(na), Some(nb), Some(nc)) => Some([normals[na], normals[nb], normals[nc]]),
+            _ => None,
+          };
+          let uvs = match (a.2, b.2, c.2) {
+            (Some(ta), Some(tb), Some(tc)) => Some([uvs[ta], uvs[tb], uvs[tc]]),
+            _ => None,
+          };
+
+          triangles.push(Triangle {
+            vertices: [positions[a.0], positions[b.0], positions[c.0]],
+            normals,
+            uvs,
+            material: Arc::clone(&material),
+          });
+        }
+      }
+      "mtllib" => {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for name in &args {
+          library.extend(load_mtl(&dir.join(name))?);
+        }
+      }
+      "usemtl" => {
+        let name = args.join(" ");
+        material = library
+          .get(&name)
+          .cloned()
+          .ok_or_else(|| error(line, format!("unknown material `{name}`")))?;
+      }
+      _ => {}
+    }
+  }
+
+  if triangles.is_empty() {
+    return Err(error(0, "no faces".to_string()));
+  }
+
+  Ok(Mesh::new(triangles))
+}
+
+// material properties collected from a `newmtl` block
+struct MtlEntry {
+  diffuse    : Vec3,
+  diffuse_map: Option<SharedTexture>,
+  specular   : Vec3,
+  emission   : Vec3,
+  transmit   : Vec3,
+  shininess  : f64,
+  ior        : f64,
+  dissolve   : f64,
+  illum      : u32,
+}
+
+impl Default for MtlEntry {
+  fn default() -> Self {
+    MtlEntry {
+      diffuse    : Vec3::new(0.8, 0.8, 0.8),
+      diffuse_map: None,
+      specular   : Vec3::zero(),
+      emission   : Vec3::zero(),
+      transmit   : Vec3::new(1.0, 1.0, 1.0),
+      shininess  : 0.0,
+      ior        : 1.5,
+      dissolve   : 1.0,
+      illum      : 2,
+    }
+  }
+}
+
+impl MtlEntry {
+  // picks the closest of our materials for the illumination model
+  fn to_material(&self) -> SharedMaterial {
+    let emission_peak = self.emission.x.max(self.emission.y).max(self.emission.z);
+    if emission_peak > 0.0 {
+      return Arc::new(DiffuseLight {
+        color: constant(color(self.emission / emission_peak)),
+        intensity: emission_peak,
+      });
+    }
+
+    // illum 4, 6, 7 are the glass models, dissolve below 1 is transparency
+    if matches!(self.illum, 4 | 6 | 7) || self.dissolve < 1.0 {
+      return Arc::new(Dielectric {
+        albedo: constant(color(self.transmit)),
+        refraction_index: self.ior.max(1.0),
+      });
+    }
+
+    // illum 3 is ray traced reflection, a dark diffuse with a bright specular reads as metal
+    let specular_peak = self.specular.x.max(self.specular.y).max(self.specular.z);
+    let diffuse_peak  = self.diffuse.x.max(self.diffuse.y).max(self.diffuse.z);
+    if self.illum == 3 || (specular_peak > 0.5 && diffuse_peak < 0.1) {
+      // blinn-phong exponent to roughness
+      let fuzz = (2.0 / (self.shininess + 2.0)).sqrt().clamp(0.0, 1.0);
+      return Arc::new(BasicMetal { albedo: constant(color(self.specular)), fuzz });
+    }
+
+    let albedo = self.diffuse_map.clone().unwrap_or_else(|| constant(color(self.diffuse)));
+    Arc::new(Solid { albedo })
+  }
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, SharedMaterial>, ObjError> {
+  let source = read(path)?;
+  let error = |line: usize, message: String| ObjError { file: path.to_path_buf(), line, message };
+
+  let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+  for (index, raw) in source.lines().enumerate() {
+    let line = index + 1;
+    let text = raw.split('#').next().unwrap_or("");
+    let mut tokens = text.split_whitespace();
+
+    let Some(keyword) = tokens.next() else {
+      continue;
+    };
+    let args: Vec<&str> = tokens.collect();
+
+    if keyword == "newmtl" {
+      entries.push((args.join(" "), MtlEntry::default()));
+      continue;
+    }
+
+    let Some((_, entry)) = entries.last_mut() else {
+      return Err(error(line, format!("`{keyword}` before any newmtl")));
+    };
+
+    let vector = |args: &[&str]| numbers::<3>(args).map(|[x, y, z]| Vec3::new(x, y, z)).map_err(|m| error(line, m));
+    let scalar = |args: &[&str]| numbers::<1>(args).map(|[x]| x).map_err(|m| error(line, m));
+    match keyword {
+      "Kd"    => entry.diffuse   = vector(&args)?,
+      "Ks"    => entry.specular  = vector(&args)?,
+      "Ke"    => entry.emission  = vector(&args)?,
+      "Tf"    => entry.transmit  = vector(&args)?,
+      "Ns"    => entry.shininess = scalar(&args)?,
+      "Ni"    => entry.ior       = scalar(&args)?,
+      "d"     => entry.dissolve  = scalar(&args)?,
+      "Tr"    => entry.dissolve  = 1.0 - scalar(&args)?,
+      "illum" => entry.illum     = scalar(&args)? as u32,
+      // options such as `-s` come before the file name
+      "map_Kd" => {
+        let file = args.last().ok_or_else(|| error(line, "missing texture path".to_string()))?;
+        let dir  = path.parent().unwrap_or(Path::new("."));
+        let texture = ImageTexture::load(&dir.join(file), WrapMode::Repeat).map_err(|e| error(line, e.to_string()))?;
+        entry.diffuse_map = Some(Arc::new(texture));
+      }
+      _ => {}
+    }
+  }
+
+  Ok(entries.into_iter().map(|(name, entry)| (name, entry.to_material())).collect())
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+  std::fs::read_to_string(path).map_err(|e| ObjError {
+    file: path.to_path_buf(),
+    line: 0,
+    message: e.to_string(),
+  })
+}
+
+fn numbers<const N: usize>(args: &[&str]) -> Result<[f64; N], String> {
+  if args.len() != N {
+    return Err(format!("expected {N} numbers, found {}", args.len()));
+  }
+
+  let mut values = [0.0; N];
+  for (value, arg) in values.iter_mut().zip(args) {
+    *value = arg
+      .parse::<f64>()
+      .ok()
+      .filter(|v| v.is_finite())
+      .ok_or_else(|| format!("invalid number `{arg}`"))?;
+  }
+  Ok(values)
+}
+
+// resolves a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner to zero-based
+// (position, normal, uv) indices
+fn face_vertex(text: &str, positions: usize, uvs: usize, normals: usize) -> Result<(usize, Option<usize>, Option<usize>), String> {
+  let mut parts = text.split('/');
+  let position = resolve(parts.next(), positions, text)?.ok_or_else(|| format!("missing vertex index in `{text}`"))?;
+  let uv       = resolve(parts.next(), uvs, text)?;
+  let normal   = resolve(parts.next(), normals, text)?;
+  Ok((position, normal, uv))
+}
+
+// obj indices are 1-based, negative ones count back from the last element
+fn resolve(part: Option<&str>, count: usize, text: &str) -> Result<Option<usize>, String> {
+  let Some(part) = part.filter(|p| !p.is_empty()) else {
+    return Ok(None);
+  };
+
+  let index: i64 = part.parse().map_err(|_| format!("invalid index in `{text}`"))?;
+  let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+  if resolved < 0 || resolved >= count as i64 {
+    return Err(format!("index {index} out of range in `{text}`"));
+  }
+  Ok(Some(resolved as usize))
+}
+
+fn color(v: Vec3) -> Color {
+  let v = v.clamp(0.0, 1.0);
+  Color::rgb(v.x, v.y, v.z)
+}
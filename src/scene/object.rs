@@ -1,21 +1,43 @@
 use std::f64;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 use crate::Vec3;
 use crate::Point3;
 use crate::Ray;
-use crate::math::EPSILON;
+use crate::math::{ EPSILON, Transform };
+use crate::scene::bvh::{ Aabb, Bvh };
 use crate::scene::material::Material;
 
 pub struct HitRecord<'a> {
-  pub t       : f64,
-  pub point   : Point3,
-  pub normal  : Vec3,
-  pub material: &'a dyn Material,
+  pub t         : f64,
+  pub point     : Point3,
+  pub normal    : Vec3, // always faces against the incoming ray
+  pub front_face: bool, // true when the ray hit the outside of the surface
+  pub uv        : (f64, f64), // texture coordinates, (0, 0) for triangles without any
+  pub material  : &'a dyn Material,
+}
+
+impl<'a> HitRecord<'a> {
+  // orients the outward normal of the surface against the ray
+  pub fn new(ray: &Ray, t: f64, point: Point3, outward_normal: Vec3, material: &'a dyn Material) -> Self {
+    let front_face = ray.dir.dot(&outward_normal) < 0.0;
+    HitRecord {
+      t,
+      point,
+      normal: if front_face { outward_normal } else { -outward_normal },
+      front_face,
+      uv: (0.0, 0.0),
+      material,
+    }
+  }
 }
 
 pub trait Hittable {
-  fn hit(&self, ray: &Ray) -> Option<HitRecord>;
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>>;
+
+  // None for unbounded objects such as planes
+  fn bounding_box(&self) -> Option<Aabb>;
 }
 
 // Object enum and variants
@@ -24,11 +46,14 @@ pub enum Object {
   Sphere(Sphere),
   Plane(Plane),
   Cylinder(Cylinder),
+  Triangle(Triangle),
+  Mesh(Mesh),
+  Instance(Instance),
 }
 
 pub struct Sphere {
   pub center  : Point3,
-  pub radius  : f64,
+  pub radius  : f64, // a negative radius points the normals inward, for hollow dielectrics
   pub material: Arc<dyn Material + Send + Sync>,
 }
 
@@ -48,20 +73,125 @@ pub struct Cylinder {
   pub bottom_material: Arc<dyn Material + Sync + Send>,
 }
 
+// `normals` are per-vertex shading normals, `uvs` per-vertex texture coordinates
+pub struct Triangle {
+  pub vertices: [Point3; 3],
+  pub normals : Option<[Vec3; 3]>,
+  pub uvs     : Option<[(f64, f64); 3]>,
+  pub material: Arc<dyn Material + Send + Sync>,
+}
+
+// triangles sharing their own bounding volume hierarchy, so the scene tree
+// only sees one object per mesh
+pub struct Mesh {
+  triangles: Vec<Triangle>,
+  bvh      : Bvh,
+  bounds   : Aabb,
+}
+
+impl Mesh {
+  pub fn new(triangles: Vec<Triangle>) -> Self {
+    let bvh = Bvh::build(&triangles);
+    let bounds = triangles
+      .iter()
+      .filter_map(|t| t.bounding_box())
+      .fold(Aabb::empty(), |b, t| b.union(&t));
+    Mesh { triangles, bvh, bounds }
+  }
+
+  pub fn triangles(&self) -> &[Triangle] {
+    &self.triangles
+  }
+}
+
+// shared geometry placed in the world by a transform, with an optional material
+// replacing the one of every surface it hits
+pub struct Instance {
+  object  : Arc<dyn Hittable + Send + Sync>,
+  to_world: Transform,
+  material: Option<Arc<dyn Material + Send + Sync>>,
+  bounds  : Option<Aabb>,
+}
+
+impl Instance {
+  pub fn new(
+    object: Arc<dyn Hittable + Send + Sync>,
+    to_world: Transform,
+    material: Option<Arc<dyn Material + Send + Sync>>,
+  ) -> Self {
+    let bounds = object.bounding_box().map(|b| b.transformed(&to_world));
+    Instance { object, to_world, material, bounds }
+  }
+}
+
+// texture mappings
+
+impl Sphere {
+  // longitude and latitude, v = 0 at the bottom pole and u = 0 on the -x side
+  fn uv(&self, point: Point3) -> (f64, f64) {
+    let p = (point - self.center) / self.radius.abs();
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi   = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+  }
+}
+
+impl Plane {
+  // distance from the anchor along two directions in the plane, one texture tile per unit
+  fn uv(&self, point: Point3) -> (f64, f64) {
+    let (tangent, bitangent) = self.normal.basis();
+    let d = point - self.anchor;
+    (d.dot(&tangent), d.dot(&bitangent))
+  }
+}
+
+impl Cylinder {
+  // angle around the axis and height along it, both in [0, 1]
+  fn body_uv(&self, point: Point3, height: f64) -> (f64, f64) {
+    let (tangent, bitangent) = self.orientation.basis();
+    let d = point - self.center;
+    let angle = d.dot(&bitangent).atan2(d.dot(&tangent));
+    (angle / (2.0 * PI) + 0.5, height / self.height)
+  }
+
+  // the cap disk fills the unit square, `offset` is measured from the cap center
+  fn cap_uv(&self, offset: Vec3) -> (f64, f64) {
+    let (tangent, bitangent) = self.orientation.basis();
+    (
+      0.5 + offset.dot(&tangent) / (2.0 * self.radius),
+      0.5 + offset.dot(&bitangent) / (2.0 * self.radius),
+    )
+  }
+}
+
 // intersection implementations
 
 impl Hittable for Object {
-  fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
     match self {
       Object::Sphere(s)   => s.hit(ray),
       Object::Plane(p)    => p.hit(ray),
       Object::Cylinder(c) => c.hit(ray),
+      Object::Triangle(t) => t.hit(ray),
+      Object::Mesh(m)     => m.hit(ray),
+      Object::Instance(i) => i.hit(ray),
+    }
+  }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    match self {
+      Object::Sphere(s)   => s.bounding_box(),
+      Object::Plane(p)    => p.bounding_box(),
+      Object::Cylinder(c) => c.bounding_box(),
+      Object::Triangle(t) => t.bounding_box(),
+      Object::Mesh(m)     => m.bounding_box(),
+      Object::Instance(i) => i.bounding_box(),
     }
   }
 }
 
 impl Hittable for Sphere {
-  fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
     // direction, origin and vector from ray to center
     let oc        = self.center - ray.o;
     let dir         = ray.dir;
@@ -84,12 +214,9 @@ impl Hittable for Sphere {
       let point: Point3 = ray.at(t1);
       let normal = ((point - self.center) / self.radius).unit();
 
-      return Some(HitRecord {
-        t: t1,
-        point,
-        normal,
-        material: self.material.as_ref(),
-      });
+      let mut rec = HitRecord::new(ray, t1, point, normal, self.material.as_ref());
+      rec.uv = self.uv(point);
+      return Some(rec);
     }
 
     // if d == 0.0 equation yields the same root twice
@@ -99,21 +226,22 @@ impl Hittable for Sphere {
       let point : Point3 = ray.at(t2);
       let normal = ((point - self.center) / self.radius).unit();
 
-      return Some(HitRecord {
-        t: t2,
-        point,
-        normal,
-        material: self.material.as_ref(),
-      });
+      let mut rec = HitRecord::new(ray, t2, point, normal, self.material.as_ref());
+      rec.uv = self.uv(point);
+      return Some(rec);
     }
 
     None
   }
 
+  fn bounding_box(&self) -> Option<Aabb> {
+    let r = Vec3::new(self.radius, self.radius, self.radius);
+    Some(Aabb::new(self.center - r, self.center + r))
+  }
 }
 
 impl Hittable for Plane {
-  fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
     let denominator = ray.dir.dot(&self.normal);
     
     if denominator.abs() < EPSILON {
@@ -123,22 +251,23 @@ impl Hittable for Plane {
 
     let t = (self.anchor - ray.o).dot(&self.normal) / denominator;
     if t < EPSILON {
-      return None;
+      None
     } else {
       let point : Point3 = ray.at(t);
 
-      Some(HitRecord {
-        t,
-        point,
-        normal: self.normal,
-        material: self.material.as_ref(),
-      })
+      let mut rec = HitRecord::new(ray, t, point, self.normal, self.material.as_ref());
+      rec.uv = self.uv(point);
+      Some(rec)
     }
   }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    None
+  }
 }
 
 impl Hittable for Cylinder {
-  fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
     // to factor out orientation and position of the cylinder,
     // we use projection math to determine whether the ray intersects 
     let oc  = ray.o - self.center;
@@ -150,7 +279,7 @@ impl Hittable for Cylinder {
     // oc component perpendicular to cylinder axis (radial offset)
     let m = oc - oc.dot(&v) * v;
     
-    let mut closest_hit: Option<HitRecord> = None;
+    let mut closest_hit: Option<HitRecord<'_>> = None;
     let mut closest_t = f64::INFINITY;
 
     // reduced coefficients and discriminant
@@ -180,12 +309,9 @@ impl Hittable for Cylinder {
 
           if t < closest_t {
             closest_t = t;
-            closest_hit = Some(HitRecord {
-              t,
-              point,
-              normal,
-              material: self.body_material.as_ref(),
-            })
+            let mut rec = HitRecord::new(ray, t, point, normal, self.body_material.as_ref());
+            rec.uv = self.body_uv(point, height);
+            closest_hit = Some(rec);
           }
         }
       }
@@ -211,16 +337,14 @@ impl Hittable for Cylinder {
         let dist2 = (hit.point - plane.anchor).length_squared();
         if dist2 <= self.radius * self.radius && hit.t < closest_t {
           closest_t   = hit.t;
-          closest_hit = Some(HitRecord {
-            t      : hit.t,
-            point  : hit.point,
-            normal : plane.normal,
-            material: if plane.normal == v {
-              self.top_material.as_ref()
-            } else {
-              self.bottom_material.as_ref()
-            },
-          }); 
+          let material = if plane.normal == v {
+            self.top_material.as_ref()
+          } else {
+            self.bottom_material.as_ref()
+          };
+          let mut rec = HitRecord::new(ray, hit.t, hit.point, plane.normal, material);
+          rec.uv = self.cap_uv(hit.point - plane.anchor);
+          closest_hit = Some(rec);
         }
       }
     }
@@ -228,4 +352,111 @@ impl Hittable for Cylinder {
     closest_hit
 
   }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    // the caps are disks, their extent along each axis shrinks as the axis aligns with it
+    let v = self.orientation;
+    let e = self.radius * Vec3::new(
+      (1.0 - v.x * v.x).max(0.0).sqrt(),
+      (1.0 - v.y * v.y).max(0.0).sqrt(),
+      (1.0 - v.z * v.z).max(0.0).sqrt(),
+    );
+    let top = self.center + self.height * v;
+    Some(Aabb::new(self.center - e, self.center + e).union(&Aabb::new(top - e, top + e)))
+  }
+}
+
+impl Hittable for Triangle {
+  // möller-trumbore, solves for the barycentric coordinates and t at once
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+    let [v0, v1, v2] = self.vertices;
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+
+    let p = ray.dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < EPSILON {
+      return None; // ray is parallel to the triangle
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.o - v0;
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+      return None;
+    }
+
+    let q = s.cross(&e1);
+    let v = ray.dir.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+      return None;
+    }
+
+    let t = e2.dot(&q) * inv_det;
+    if t < EPSILON {
+      return None;
+    }
+
+    // sidedness comes from the geometric normal, shading uses the interpolated one,
+    // vertex normals win over the winding order when they disagree about the outside
+    let w = 1.0 - u - v;
+    let mut outward = e1.cross(&e2).unit();
+    let shading = self.normals.map(|[n0, n1, n2]| (w * n0 + u * n1 + v * n2).unit());
+    if let Some(shading) = shading
+      && shading.dot(&outward) < 0.0 {
+      outward = -outward;
+    }
+
+    let mut rec = HitRecord::new(ray, t, ray.at(t), outward, self.material.as_ref());
+    if let Some(shading) = shading {
+      rec.normal = if rec.front_face { shading } else { -shading };
+    }
+    if let Some([uv0, uv1, uv2]) = self.uvs {
+      rec.uv = (
+        w * uv0.0 + u * uv1.0 + v * uv2.0,
+        w * uv0.1 + u * uv1.1 + v * uv2.1,
+      );
+    }
+
+    Some(rec)
+  }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    // padded so axis-aligned triangles do not produce flat boxes
+    let pad = Vec3::new(1e-6, 1e-6, 1e-6);
+    let [v0, v1, v2] = self.vertices;
+    let bounds = Aabb::new(v0, v1).grow(v2);
+    Some(Aabb::new(bounds.min - pad, bounds.max + pad))
+  }
+}
+
+impl Hittable for Mesh {
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+    self.bvh.cast(&self.triangles, ray).map(|(_, hit)| hit)
+  }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    Some(self.bounds)
+  }
+}
+
+impl Hittable for Instance {
+  // the ray is moved into object space without normalizing its direction,
+  // so the t found there is also the distance along the world ray
+  fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+    let to_local = self.to_world.inverted();
+    let local = Ray::new(to_local.point(ray.o), to_local.vector(ray.dir));
+    let mut rec = self.object.hit(&local)?;
+
+    rec.point  = ray.at(rec.t);
+    rec.normal = self.to_world.normal(rec.normal).unit();
+    if let Some(material) = &self.material {
+      rec.material = material.as_ref();
+    }
+    Some(rec)
+  }
+
+  fn bounding_box(&self) -> Option<Aabb> {
+    self.bounds
+  }
 }
@@ -0,0 +1,851 @@
+// parser for miniRT-style `.rt` scene files
+//
+// every non-empty line starts with an identifier followed by whitespace separated
+// fields, vectors and colors are comma separated triples:
+//
+//   A  0.2 255,255,255                            ambient ratio, color
+//   C  -50,0,20 0,0,1 70 [0.5 40 [6]]             position, orientation, fov, optionally
+//                                                 aperture, focus distance and blade count
+//   L  -40,0,30 0.7 255,255,255                   position, brightness, color
+//   sp 0,0,20 20 255,0,0                          center, diameter, color
+//   pl 0,0,0 0,1,0 255,0,225                      point, normal, color
+//   cy 50,0,20.6 0,0,1 14.2 21.42 10,0,255        center, axis, diameter, height, color
+//
+// on top of the miniRT identifiers, lights can also be directional or spots:
+//
+//   ld 0,-1,0 0.5 255,255,255                     direction, brightness, color
+//   ls 0,10,0 0,-1,0 0.8 20 30 255,255,255        position, direction, brightness,
+//                                                 inner and outer half-angle, color
+//
+// point and spot brightness is the part of their color reaching a surface that faces
+// them 10 units away, closer surfaces receive more and farther ones less
+//
+// objects can follow their color with a material, they are diffuse otherwise:
+//
+//   sp 0,0,20 20 255,255,255 metal 0.1            fuzz in [0, 1]
+//   sp 0,0,20 20 255,255,255 pbr 1 0.3            metallic and roughness in [0, 1],
+//                                                 as in glTF materials
+//   sp 0,0,20 20 255,255,255 glass 1.5            refraction index, a negative
+//                                                 diameter makes a hollow sphere
+//   sp 0,0,20 20 255,255,255 light 4              emitted intensity, turns the object
+//                                                 into an area light
+//
+// instead of a plain color, objects can use a checkerboard or an image file, relative
+// to the `.rt` file, wrapped with repeat (default), clamp or mirror:
+//
+//   sp 0,0,20 20 checker 255,255,255 0,0,0 8      two colors, squares per texture unit
+//   pl 0,0,0 0,1,0 image floor.png mirror         png or jpeg path, optional wrap mode
+//
+// or a procedural pattern going from a first to a second color, with noise, turbulence,
+// marble, wood or worley:
+//
+//   sp 0,0,20 20 marble 30,30,30 240,240,240 0.5  pattern, colors, features per unit
+//
+// triangles and Wavefront meshes, the mesh path is relative to the `.rt` file and
+// the color is used for faces without an MTL material:
+//
+//   tr 0,0,0 1,0,0 0,1,0 255,255,255              three vertices, color
+//   ob models/teapot.obj 0,0,-5 1.5 200,200,200   path, offset, scale, color
+//
+// objects take optional modifiers after their material, applied in the order written,
+// scale and rotate keep the object in place, turning it about the middle of its bounds
+// (the point of a plane) wherever earlier modifiers moved it:
+//
+//   sp 0,0,20 10 255,0,0 scale 2,1,1              scale factors, none of them zero
+//   cy 0,0,0 0,1,0 2 4 255,255,255 rotate 0,0,45  degrees around X, then Y, then Z
+//   tr 0,0,0 1,0,0 0,1,0 0,0,255 move 0,2,0       offset
+//
+// `def` names an object without adding it to the scene, `in` places an instance of it,
+// optionally with a color and material replacing its own:
+//
+//   def bunny ob models/bunny.obj 0,0,0 1 200,200,200
+//   in bunny move 5,0,0 rotate 0,90,0             name, modifiers
+//   in bunny 255,0,0 metal 0.2 move -5,0,0        name, color, material, modifiers
+//
+// the sky gradient seen by escaping rays can be replaced by a flat color, another
+// gradient or an equirectangular `.hdr` or `.exr` image lighting the scene, relative
+// to the `.rt` file:
+//
+//   B  0,0,0                                      background color
+//   B  gradient 255,255,255 76,127,255            colors straight down and straight up
+//   B  image sky.hdr 90 1.5                       path, optionally the degrees the map
+//                                                 is turned around Y and its intensity
+//
+// `#` starts a comment that runs until the end of the line
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::math::{ Point3, Transform, Vec3 };
+use crate::scene::{ Scene, AmbientLight };
+use crate::scene::environment::{ Environment, EnvironmentMap };
+use crate::scene::light::{ Light, PointLight, DirectionalLight, SpotLight };
+use crate::scene::obj::{ load_obj, ObjError, ObjPlacement };
+use crate::scene::object::{ Hittable, Object, Sphere, Plane, Cylinder, Triangle, Instance };
+use crate::scene::material::{ Material, Solid, BasicMetal, Dielectric, DiffuseLight, MetallicRoughness };
+use crate::scene::texture::{ constant, Checker, ImageTexture, Pattern, Procedural, SharedTexture, TextureError, WrapMode };
+
+// tolerance used when checking that orientation vectors are normalized
+const UNIT_TOLERANCE: f64 = 1e-3;
+// smallest value accepted for sizes that must be strictly positive
+const EPSILON_POSITIVE: f64 = f64::MIN_POSITIVE;
+
+// distance at which point and spot lights deliver their `.rt` brightness
+const LIGHT_REFERENCE_DISTANCE: f64 = 10.0;
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+  Io(std::io::Error),
+  UnknownIdentifier(String),
+  MissingField(&'static str),
+  TrailingField(String),
+  InvalidNumber(String),
+  InvalidTuple(String),
+  OutOfRange { field: &'static str, value: f64, min: f64, max: f64 },
+  OutOfOpenRange { field: &'static str, value: f64, min: f64, max: f64 },
+  NotInteger { field: &'static str, value: f64 },
+  NotNormalized(&'static str),
+  Zero(&'static str),
+  Mesh(ObjError),
+  Texture(TextureError),
+  Duplicate(&'static str),
+  Missing(&'static str),
+  UnknownDefinition(String),
+  DuplicateDefinition(String),
+  NotAnObject(String),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+  pub line  : usize, // 1-based, 0 when the error is not tied to a line
+  pub column: usize, // 1-based
+  pub kind  : ParseErrorKind,
+}
+
+impl fmt::Display for ParseErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseErrorKind::Io(e)                     => write!(f, "{e}"),
+      ParseErrorKind::UnknownIdentifier(id)     => write!(f, "unknown identifier `{id}`"),
+      ParseErrorKind::MissingField(field)       => write!(f, "missing {field}"),
+      ParseErrorKind::TrailingField(token)      => write!(f, "unexpected field `{token}`"),
+      ParseErrorKind::InvalidNumber(token)      => write!(f, "invalid number `{token}`"),
+      ParseErrorKind::InvalidTuple(token)       => write!(f, "expected three comma separated values, found `{token}`"),
+      ParseErrorKind::OutOfRange { field, value, min, max } =>
+        write!(f, "{field} {value} is out of range [{min}, {max}]"),
+      ParseErrorKind::OutOfOpenRange { field, value, min, max } =>
+        write!(f, "{field} {value} is out of range ({min}, {max})"),
+      ParseErrorKind::NotInteger { field, value } =>
+        write!(f, "{field} {value} must be a whole number"),
+      ParseErrorKind::NotNormalized(field)      => write!(f, "{field} must be a normalized vector"),
+      ParseErrorKind::Zero(field)               => write!(f, "{field} cannot be zero"),
+      ParseErrorKind::Mesh(e)                   => write!(f, "{e}"),
+      ParseErrorKind::Texture(e)                => write!(f, "{e}"),
+      ParseErrorKind::Duplicate(element)        => write!(f, "{element} can only be declared once"),
+      ParseErrorKind::Missing(element)          => write!(f, "scene has no {element}"),
+      ParseErrorKind::UnknownDefinition(name)   => write!(f, "no object defined as `{name}`"),
+      ParseErrorKind::DuplicateDefinition(name) => write!(f, "`{name}` is already defined"),
+      ParseErrorKind::NotAnObject(id)           => write!(f, "`{id}` does not describe an object"),
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.line == 0 {
+      write!(f, "{}", self.kind)
+    } else {
+      write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+// a whitespace separated token and the column it starts at
+struct Token<'a> {
+  text  : &'a str,
+  column: usize,
+}
+
+// cursor over the tokens of a single line
+struct Fields<'a> {
+  line  : usize,
+  end   : usize, // column right after the last character, for missing fields
+  tokens: std::vec::IntoIter<Token<'a>>,
+}
+
+impl<'a> Fields<'a> {
+  fn new(line: usize, text: &'a str) -> Self {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (i, c) in text.char_indices() {
+      if c.is_whitespace() {
+        if let Some(s) = start.take() {
+          tokens.push(Token { text: &text[s..i], column: s + 1 });
+        }
+      } else if start.is_none() {
+        start = Some(i);
+      }
+    }
+    if let Some(s) = start {
+      tokens.push(Token { text: &text[s..], column: s + 1 });
+    }
+
+    Fields {
+      line,
+      end: text.trim_end().len() + 1,
+      tokens: tokens.into_iter(),
+    }
+  }
+
+  fn error(&self, column: usize, kind: ParseErrorKind) -> ParseError {
+    ParseError { line: self.line, column, kind }
+  }
+
+  fn next(&mut self, field: &'static str) -> Result<Token<'a>, ParseError> {
+    self.tokens
+      .next()
+      .ok_or_else(|| self.error(self.end, ParseErrorKind::MissingField(field)))
+  }
+
+  fn number(&mut self, field: &'static str) -> Result<f64, ParseError> {
+    let token = self.next(field)?;
+    parse_number(token.text)
+      .ok_or_else(|| self.error(token.column, ParseErrorKind::InvalidNumber(token.text.to_string())))
+  }
+
+  fn number_in(&mut self, field: &'static str, min: f64, max: f64) -> Result<f64, ParseError> {
+    let column = self.peek_column();
+    let value = self.number(field)?;
+    if !(min..=max).contains(&value) {
+      return Err(self.error(column, ParseErrorKind::OutOfRange { field, value, min, max }));
+    }
+    Ok(value)
+  }
+
+  // whole number in [min, max], written without a fractional part
+  fn integer_in(&mut self, field: &'static str, min: u32, max: u32) -> Result<u32, ParseError> {
+    let column = self.peek_column();
+    let value = self.number_in(field, min as f64, max as f64)?;
+    if value.fract() != 0.0 {
+      return Err(self.error(column, ParseErrorKind::NotInteger { field, value }));
+    }
+    Ok(value as u32)
+  }
+
+  // like `number_in` with both bounds excluded
+  fn number_between(&mut self, field: &'static str, min: f64, max: f64) -> Result<f64, ParseError> {
+    let column = self.peek_column();
+    let value = self.number(field)?;
+    if !(value > min && value < max) {
+      return Err(self.error(column, ParseErrorKind::OutOfOpenRange { field, value, min, max }));
+    }
+    Ok(value)
+  }
+
+  fn positive(&mut self, field: &'static str) -> Result<f64, ParseError> {
+    self.number_in(field, EPSILON_POSITIVE, f64::MAX)
+  }
+
+  // three comma separated numbers, each component is reported at its own column
+  fn triple(&mut self, field: &'static str) -> Result<([f64; 3], [usize; 3]), ParseError> {
+    let token = self.next(field)?;
+    let parts: Vec<&str> = token.text.split(',').collect();
+    if parts.len() != 3 {
+      return Err(self.error(token.column, ParseErrorKind::InvalidTuple(token.text.to_string())));
+    }
+
+    let mut values  = [0.0; 3];
+    let mut columns = [0; 3];
+    let mut offset  = 0;
+    for (i, part) in parts.iter().enumerate() {
+      columns[i] = token.column + offset;
+      values[i] = parse_number(part)
+        .ok_or_else(|| self.error(columns[i], ParseErrorKind::InvalidNumber(part.to_string())))?;
+      offset += part.len() + 1;
+    }
+
+    Ok((values, columns))
+  }
+
+  fn point(&mut self, field: &'static str) -> Result<Point3, ParseError> {
+    let ([x, y, z], _) = self.triple(field)?;
+    Ok(Point3::new(x, y, z))
+  }
+
+  // orientation vectors have every component in [-1, 1] and unit length
+  fn direction(&mut self, field: &'static str) -> Result<Vec3, ParseError> {
+    let column = self.peek_column();
+    let (values, columns) = self.triple(field)?;
+    for (value, column) in values.into_iter().zip(columns) {
+      if !(-1.0..=1.0).contains(&value) {
+        return Err(self.error(column, ParseErrorKind::OutOfRange { field, value, min: -1.0, max: 1.0 }));
+      }
+    }
+
+    let v = Vec3::new(values[0], values[1], values[2]);
+    if (v.length() - 1.0).abs() > UNIT_TOLERANCE {
+      return Err(self.error(column, ParseErrorKind::NotNormalized(field)));
+    }
+    Ok(v.unit())
+  }
+
+  // colors are written as 0-255 integers per channel
+  fn color(&mut self, field: &'static str) -> Result<Color, ParseError> {
+    let (values, columns) = self.triple(field)?;
+    for (value, column) in values.into_iter().zip(columns) {
+      if !(0.0..=255.0).contains(&value) {
+        return Err(self.error(column, ParseErrorKind::OutOfRange { field, value, min: 0.0, max: 255.0 }));
+      }
+    }
+    Ok(Color::rgb(values[0] / 255.0, values[1] / 255.0, values[2] / 255.0))
+  }
+
+  // an object's color: a plain color, a checkerboard of two colors, an image file
+  // or a procedural pattern blending two colors
+  fn texture(&mut self, field: &'static str, base_dir: &Path) -> Result<SharedTexture, ParseError> {
+    let keyword = self.tokens.as_slice().first().map(|t| t.text);
+    if let Some(pattern) = keyword.and_then(Pattern::from_name) {
+      self.tokens.next();
+      let low   = self.color("pattern color")?;
+      let high  = self.color("pattern color")?;
+      let scale = self.positive("pattern scale")?;
+      return Ok(Arc::new(Procedural::new(pattern, low, high, scale)));
+    }
+
+    match keyword {
+      Some("checker") => {
+        self.tokens.next();
+        let even  = self.color("checker color")?;
+        let odd   = self.color("checker color")?;
+        let scale = self.positive("checker scale")?;
+        Ok(Arc::new(Checker { even: constant(even), odd: constant(odd), scale }))
+      }
+      Some("image") => {
+        self.tokens.next();
+        let column = self.peek_column();
+        let file   = self.text("texture path")?;
+        let wrap   = self.tokens.as_slice().first().and_then(|t| WrapMode::from_name(t.text));
+        if wrap.is_some() {
+          self.tokens.next();
+        }
+        let texture = ImageTexture::load(&base_dir.join(file), wrap.unwrap_or_default())
+          .map_err(|e| self.error(column, ParseErrorKind::Texture(e)))?;
+        Ok(Arc::new(texture))
+      }
+      _ => Ok(constant(self.color(field)?)),
+    }
+  }
+
+  // optional material keyword after an object's color
+  fn material(&mut self, color: SharedTexture) -> Result<Arc<dyn Material + Send + Sync>, ParseError> {
+    let keyword = self.tokens.as_slice().first().map(|t| t.text);
+    match keyword {
+      Some("metal") => {
+        self.tokens.next();
+        let fuzz = self.number_in("metal fuzz", 0.0, 1.0)?;
+        Ok(Arc::new(BasicMetal { albedo: color, fuzz }))
+      }
+      Some("pbr") => {
+        self.tokens.next();
+        let metallic  = self.number_in("metallic", 0.0, 1.0)?;
+        let roughness = self.number_in("roughness", 0.0, 1.0)?;
+        Ok(Arc::new(MetallicRoughness { base_color: color, metallic, roughness, metallic_roughness: None }))
+      }
+      Some("glass") => {
+        self.tokens.next();
+        let refraction_index = self.number_in("refraction index", 1.0, 10.0)?;
+        Ok(Arc::new(Dielectric { albedo: color, refraction_index }))
+      }
+      Some("light") => {
+        self.tokens.next();
+        let intensity = self.number_in("light intensity", 0.0, f64::MAX)?;
+        Ok(Arc::new(DiffuseLight { color, intensity }))
+      }
+      _ => Ok(solid(color)),
+    }
+  }
+
+  // what escaping rays see: a plain color, a gradient or an environment map
+  fn environment(&mut self, base_dir: &Path) -> Result<Environment, ParseError> {
+    match self.tokens.as_slice().first().map(|t| t.text) {
+      Some("gradient") => {
+        self.tokens.next();
+        let bottom = self.color("gradient color")?;
+        let top    = self.color("gradient color")?;
+        Ok(Environment::Gradient { bottom: Vec3::from(bottom), top: Vec3::from(top) })
+      }
+      Some("image") => {
+        self.tokens.next();
+        let column    = self.peek_column();
+        let file      = self.text("environment path")?;
+        let rotation  = if self.has_more() { self.number("environment rotation")? } else { 0.0 };
+        let intensity = if self.has_more() { self.number_in("environment intensity", 0.0, f64::MAX)? } else { 1.0 };
+        let map = EnvironmentMap::load(&base_dir.join(file), rotation, intensity)
+          .map_err(|e| self.error(column, ParseErrorKind::Texture(e)))?;
+        Ok(Environment::Map(map))
+      }
+      _ => Ok(Environment::Color(Vec3::from(self.color("background color")?))),
+    }
+  }
+
+  fn at_modifier(&self) -> bool {
+    matches!(self.tokens.as_slice().first().map(|t| t.text), Some("move" | "rotate" | "scale"))
+  }
+
+  // optional modifiers after an object's material, applied in the order they are written,
+  // None when there are none. rotate and scale turn the object about `pivot`, which
+  // follows it through every move
+  fn transform(&mut self, pivot: Point3) -> Result<Option<Transform>, ParseError> {
+    let mut pivot = pivot;
+    let mut transform: Option<Transform> = None;
+    while self.at_modifier() {
+      let keyword = self.tokens.next().map_or("", |t| t.text);
+      let step = match keyword {
+        "move" => {
+          let offset = self.point("offset")?;
+          pivot += offset;
+          Transform::translate(offset)
+        }
+        "rotate" => {
+          // degrees around X, then Y, then Z
+          let ([x, y, z], _) = self.triple("rotation")?;
+          let rotation = Transform::rotate(Vec3::new(1.0, 0.0, 0.0), x)
+            .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), y))
+            .then(&Transform::rotate(Vec3::new(0.0, 0.0, 1.0), z));
+          about(pivot, &rotation)
+        }
+        _ => {
+          let (factors, columns) = self.triple("scale factors")?;
+          if let Some(i) = factors.iter().position(|&f| f == 0.0) {
+            return Err(self.error(columns[i], ParseErrorKind::Zero("scale factor")));
+          }
+          about(pivot, &Transform::scale(Vec3::new(factors[0], factors[1], factors[2])))
+        }
+      };
+      transform = Some(transform.map_or(step, |t| t.then(&step)));
+    }
+    Ok(transform)
+  }
+
+  fn text(&mut self, field: &'static str) -> Result<&'a str, ParseError> {
+    Ok(self.next(field)?.text)
+  }
+
+  fn has_more(&self) -> bool {
+    !self.tokens.as_slice().is_empty()
+  }
+
+  fn peek_column(&self) -> usize {
+    self.tokens.as_slice().first().map_or(self.end, |t| t.column)
+  }
+
+  fn finish(mut self) -> Result<(), ParseError> {
+    match self.tokens.next() {
+      Some(token) => Err(self.error(token.column, ParseErrorKind::TrailingField(token.text.to_string()))),
+      None => Ok(()),
+    }
+  }
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+  text.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+struct CameraSpec {
+  position   : Point3,
+  direction  : Vec3,
+  fov_degrees: f64,
+  lens       : Option<(f64, f64, u32)>, // aperture, focus distance, blades
+}
+
+pub fn parse_file<P: AsRef<Path>>(path: P, image_width: usize, aspect_ratio: f64) -> Result<Scene, ParseError> {
+  let path = path.as_ref();
+  let source = std::fs::read_to_string(path)
+    .map_err(|e| ParseError { line: 0, column: 0, kind: ParseErrorKind::Io(e) })?;
+  parse(&source, path.parent().unwrap_or(Path::new(".")), image_width, aspect_ratio)
+}
+
+// `base_dir` is where files referenced by the scene, such as meshes, are looked up
+pub fn parse(source: &str, base_dir: &Path, image_width: usize, aspect_ratio: f64) -> Result<Scene, ParseError> {
+  let mut ambient: Option<AmbientLight> = None;
+  let mut background: Option<Environment> = None;
+  let mut camera : Option<CameraSpec>   = None;
+  let mut objects: Vec<Object>          = Vec::new();
+  let mut lights : Vec<Light>           = Vec::new();
+  let mut definitions: HashMap<&str, (Arc<dyn Hittable + Send + Sync>, Point3)> = HashMap::new();
+
+  for (index, raw) in source.lines().enumerate() {
+    let line = index + 1;
+    let text = raw.split('#').next().unwrap_or("");
+    let mut fields = Fields::new(line, text);
+
+    let Some(id) = fields.tokens.next() else {
+      continue;
+    };
+
+    match id.text {
+      "A" => {
+        if ambient.is_some() {
+          return Err(fields.error(id.column, ParseErrorKind::Duplicate("ambient light")));
+        }
+        let ratio = fields.number_in("ambient ratio", 0.0, 1.0)?;
+        let color = fields.color("ambient color")?;
+        ambient = Some(AmbientLight { ratio, color });
+      }
+      "B" => {
+        if background.is_some() {
+          return Err(fields.error(id.column, ParseErrorKind::Duplicate("background")));
+        }
+        background = Some(fields.environment(base_dir)?);
+      }
+      "C" => {
+        if camera.is_some() {
+          return Err(fields.error(id.column, ParseErrorKind::Duplicate("camera")));
+        }
+        let position    = fields.point("camera position")?;
+        let direction   = fields.direction("camera orientation")?;
+        let fov_degrees = fields.number_between("camera fov", 0.0, 180.0)?;
+        let lens = if fields.has_more() {
+          let aperture = fields.number_in("camera aperture", 0.0, f64::MAX)?;
+          let focus    = fields.positive("camera focus distance")?;
+          let blades   = if fields.has_more() { fields.integer_in("aperture blades", 0, 64)? } else { 0 };
+          Some((aperture, focus, blades))
+        } else {
+          None
+        };
+        camera = Some(CameraSpec { position, direction, fov_degrees, lens });
+      }
+      "L" => {
+        let position   = fields.point("light position")?;
+        let brightness = fields.number_in("light brightness", 0.0, 1.0)?;
+        let color      = fields.color("light color")?;
+        lights.push(Light::Point(PointLight { position, color, intensity: brightness }));
+      }
+      "ld" => {
+        let direction  = fields.direction("light direction")?;
+        let brightness = fields.number_in("light brightness", 0.0, 1.0)?;
+        let color      = fields.color("light color")?;
+        lights.push(Light::Directional(DirectionalLight { direction, color, intensity: brightness }));
+      }
+      "ls" => {
+        let position    = fields.point("light position")?;
+        let direction   = fields.direction("light direction")?;
+        let brightness  = fields.number_in("light brightness", 0.0, 1.0)?;
+        let inner_angle = fields.number_in("spot inner angle", 0.0, 90.0)?;
+        let outer_angle = fields.number_in("spot outer angle", inner_angle, 90.0)?;
+        let color       = fields.color("light color")?;
+        lights.push(Light::Spot(SpotLight {
+          position,
+          direction,
+          color,
+          intensity: brightness,
+          inner_angle,
+          outer_angle,
+        }));
+      }
+      "sp" | "pl" | "cy" | "tr" | "ob" => {
+        objects.push(object(id, &mut fields, base_dir)?);
+      }
+      "def" => {
+        let name = fields.next("definition name")?;
+        if definitions.contains_key(name.text) {
+          return Err(fields.error(name.column, ParseErrorKind::DuplicateDefinition(name.text.to_string())));
+        }
+        let kind = fields.next("definition object")?;
+        let shape = object(kind, &mut fields, base_dir)?;
+        let pivot = center(&shape);
+        definitions.insert(name.text, (Arc::new(shape), pivot));
+      }
+      "in" => {
+        let name  = fields.next("instance name")?;
+        let Some((shape, pivot)) = definitions.get(name.text) else {
+          return Err(fields.error(name.column, ParseErrorKind::UnknownDefinition(name.text.to_string())));
+        };
+        let material = if fields.has_more() && !fields.at_modifier() {
+          let texture = fields.texture("instance color", base_dir)?;
+          Some(fields.material(texture)?)
+        } else {
+          None
+        };
+        let transform = fields.transform(*pivot)?.unwrap_or_else(Transform::identity);
+        objects.push(Object::Instance(Instance::new(Arc::clone(shape), transform, material)));
+      }
+      other => {
+        return Err(fields.error(id.column, ParseErrorKind::UnknownIdentifier(other.to_string())));
+      }
+    }
+
+    fields.finish()?;
+  }
+
+  let missing = |element| ParseError { line: 0, column: 0, kind: ParseErrorKind::Missing(element) };
+  let ambient = ambient.ok_or_else(|| missing("ambient light"))?;
+  let camera  = camera.ok_or_else(|| missing("camera"))?;
+
+  let mut view = Camera::new(camera.position, camera.direction, camera.fov_degrees, aspect_ratio, image_width);
+  if let Some((aperture, focus_distance, blades)) = camera.lens {
+    view.aperture        = aperture;
+    view.focus_distance  = focus_distance;
+    view.aperture_blades = blades;
+    view.update_viewport();
+  }
+  let mut scene = Scene::new(view, ambient);
+  if let Some(environment) = background {
+    scene.environment = environment;
+  }
+  for object in objects {
+    scene.add_object(object);
+  }
+
+  // `.rt` brightness is a ratio without falloff, scale the intensity of positional
+  // lights so they deliver that ratio at the reference distance
+  for mut light in lights {
+    match &mut light {
+      Light::Point(PointLight { intensity, .. }) | Light::Spot(SpotLight { intensity, .. }) => {
+        *intensity *= LIGHT_REFERENCE_DISTANCE * LIGHT_REFERENCE_DISTANCE;
+      }
+      Light::Directional(_) => {}
+    }
+    scene.add_light(light);
+  }
+
+  Ok(scene)
+}
+
+// an object line after its identifier, with the modifiers that place it in the world
+fn object(id: Token, fields: &mut Fields, base_dir: &Path) -> Result<Object, ParseError> {
+  let object = match id.text {
+    "sp" => {
+      let center   = fields.point("sphere center")?;
+      let column   = fields.peek_column();
+      let diameter = fields.number("sphere diameter")?;
+      if diameter == 0.0 {
+        return Err(fields.error(column, ParseErrorKind::Zero("sphere diameter")));
+      }
+      let texture  = fields.texture("sphere color", base_dir)?;
+      let material = fields.material(texture)?;
+      Object::Sphere(Sphere {
+        center,
+        radius: diameter / 2.0,
+        material,
+      })
+    }
+    "pl" => {
+      let anchor   = fields.point("plane point")?;
+      let normal   = fields.direction("plane normal")?;
+      let texture  = fields.texture("plane color", base_dir)?;
+      let material = fields.material(texture)?;
+      Object::Plane(Plane { anchor, normal, material })
+    }
+    "cy" => {
+      let center      = fields.point("cylinder center")?;
+      let orientation = fields.direction("cylinder axis")?;
+      let diameter    = fields.positive("cylinder diameter")?;
+      let height      = fields.positive("cylinder height")?;
+      let texture     = fields.texture("cylinder color", base_dir)?;
+      let material    = fields.material(texture)?;
+      // `.rt` files give the center of the cylinder, ours is anchored at the bottom cap
+      Object::Cylinder(Cylinder {
+        center: center - orientation * (height / 2.0),
+        radius: diameter / 2.0,
+        height,
+        orientation,
+        body_material: Arc::clone(&material),
+        top_material: Arc::clone(&material),
+        bottom_material: material,
+      })
+    }
+    "tr" => {
+      let a        = fields.point("triangle vertex")?;
+      let b        = fields.point("triangle vertex")?;
+      let c        = fields.point("triangle vertex")?;
+      let texture  = fields.texture("triangle color", base_dir)?;
+      let material = fields.material(texture)?;
+      Object::Triangle(Triangle {
+        vertices: [a, b, c],
+        normals: None,
+        uvs: None,
+        material,
+      })
+    }
+    "ob" => {
+      let column   = fields.peek_column();
+      let file     = fields.text("mesh path")?;
+      let offset   = fields.point("mesh offset")?;
+      let scale    = fields.positive("mesh scale")?;
+      let texture  = fields.texture("mesh color", base_dir)?;
+      let material = fields.material(texture)?;
+      let mesh = load_obj(&base_dir.join(file), &ObjPlacement { offset, scale }, material)
+        .map_err(|e| fields.error(column, ParseErrorKind::Mesh(e)))?;
+      Object::Mesh(mesh)
+    }
+    other => {
+      return Err(fields.error(id.column, ParseErrorKind::NotAnObject(other.to_string())));
+    }
+  };
+
+  Ok(match fields.transform(center(&object))? {
+    Some(transform) => Object::Instance(Instance::new(Arc::new(object), transform, None)),
+    None => object,
+  })
+}
+
+// where rotate and scale turn an object about, the middle of its bounds, a plane's
+// anchor, or the origin for anything else without bounds
+fn center(object: &Object) -> Point3 {
+  match object {
+    Object::Plane(plane) => plane.anchor,
+    _ => object.bounding_box().map_or(Point3::zero(), |bounds| bounds.centroid()),
+  }
+}
+
+// `step` applied with `pivot` standing still
+fn about(pivot: Point3, step: &Transform) -> Transform {
+  Transform::translate(-pivot).then(step).then(&Transform::translate(pivot))
+}
+
+fn solid(color: SharedTexture) -> Arc<dyn Material + Send + Sync> {
+  Arc::new(Solid { albedo: color })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HEADER: &str = "A 0.2 255,255,255\nC 0,0,0 0,0,1 70\n";
+
+  fn parse_str(source: &str) -> Result<Scene, ParseError> {
+    parse(source, Path::new("."), 40, 1.0)
+  }
+
+  fn error(source: &str) -> ParseError {
+    match parse_str(source) {
+      Ok(_) => panic!("`{source}` parsed"),
+      Err(e) => e,
+    }
+  }
+
+  #[test]
+  fn minimal_scene() {
+    let scene = parse_str(HEADER).unwrap();
+    assert_eq!(scene.camera.fov_degrees, 70.0);
+    assert_eq!(scene.ambient.ratio, 0.2);
+  }
+
+  #[test]
+  fn invalid_number() {
+    let e = error("A 0.2x 255,255,255");
+    assert_eq!((e.line, e.column), (1, 3));
+    assert!(matches!(e.kind, ParseErrorKind::InvalidNumber(ref token) if token == "0.2x"));
+
+    let e = error(&format!("{HEADER}sp 0,0,nan 2 255,0,0"));
+    assert_eq!((e.line, e.column), (3, 8));
+    assert!(matches!(e.kind, ParseErrorKind::InvalidNumber(ref token) if token == "nan"));
+  }
+
+  #[test]
+  fn color_out_of_range() {
+    let e = error("A 0.2 255,256,255");
+    assert_eq!((e.line, e.column), (1, 11));
+    assert!(matches!(e.kind, ParseErrorKind::OutOfRange { field: "ambient color", value: 256.0, .. }));
+
+    let e = error("A 0.2 255,255,-1");
+    assert_eq!(e.column, 15);
+  }
+
+  #[test]
+  fn orientation_not_normalized() {
+    let e = error("A 0.2 255,255,255\nC 0,0,0 0,0,0.5 70");
+    assert_eq!((e.line, e.column), (2, 9));
+    assert!(matches!(e.kind, ParseErrorKind::NotNormalized("camera orientation")));
+
+    let e = error("A 0.2 255,255,255\nC 0,0,0 0,2,0 70");
+    assert_eq!((e.line, e.column), (2, 11));
+    assert!(matches!(e.kind, ParseErrorKind::OutOfRange { field: "camera orientation", .. }));
+  }
+
+  #[test]
+  fn duplicate_ambient_and_camera() {
+    let e = error(&format!("{HEADER}  A 0.1 255,255,255"));
+    assert_eq!((e.line, e.column), (3, 3));
+    assert!(matches!(e.kind, ParseErrorKind::Duplicate("ambient light")));
+
+    let e = error(&format!("{HEADER}C 0,0,0 0,0,1 70"));
+    assert_eq!((e.line, e.column), (3, 1));
+    assert!(matches!(e.kind, ParseErrorKind::Duplicate("camera")));
+  }
+
+  #[test]
+  fn fov_bounds() {
+    for fov in ["0", "180", "-10", "200"] {
+      let e = error(&format!("A 0.2 255,255,255\nC 0,0,0 0,0,1 {fov}"));
+      assert_eq!((e.line, e.column), (2, 15));
+      assert!(matches!(e.kind, ParseErrorKind::OutOfOpenRange { field: "camera fov", .. }), "fov {fov}");
+    }
+    for fov in ["0.1", "179.9"] {
+      assert!(parse_str(&format!("A 0.2 255,255,255\nC 0,0,0 0,0,1 {fov}")).is_ok(), "fov {fov}");
+    }
+  }
+
+  #[test]
+  fn aperture_blades() {
+    let scene = parse_str("A 0.2 255,255,255\nC 0,0,0 0,0,1 70 0.5 4 6").unwrap();
+    assert_eq!(scene.camera.aperture_blades, 6);
+
+    let e = error("A 0.2 255,255,255\nC 0,0,0 0,0,1 70 0.5 4 5.7");
+    assert_eq!((e.line, e.column), (2, 24));
+    assert!(matches!(e.kind, ParseErrorKind::NotInteger { field: "aperture blades", value: 5.7 }));
+
+    let e = error("A 0.2 255,255,255\nC 0,0,0 0,0,1 70 0.5 4 65");
+    assert!(matches!(e.kind, ParseErrorKind::OutOfRange { field: "aperture blades", .. }));
+  }
+
+  #[test]
+  fn unknown_identifier() {
+    let e = error(&format!("{HEADER}\n# comment\n   box 0,0,0"));
+    assert_eq!((e.line, e.column), (5, 4));
+    assert!(matches!(e.kind, ParseErrorKind::UnknownIdentifier(ref id) if id == "box"));
+  }
+
+  #[test]
+  fn missing_and_trailing_fields() {
+    let e = error("A 0.2");
+    assert_eq!((e.line, e.column), (1, 6));
+    assert!(matches!(e.kind, ParseErrorKind::MissingField("ambient color")));
+
+    let e = error("A 0.2 255,255,255 7");
+    assert_eq!((e.line, e.column), (1, 19));
+    assert!(matches!(e.kind, ParseErrorKind::TrailingField(_)));
+
+    let e = error("A 0.2 255,255,255");
+    assert_eq!((e.line, e.column), (0, 0));
+    assert!(matches!(e.kind, ParseErrorKind::Missing("camera")));
+  }
+
+  #[test]
+  fn modifiers_turn_objects_in_place() {
+    let scene = parse_str(&format!("{HEADER}\
+      sp 0,0,20 2 255,0,0 scale 2,2,2\n\
+      cy 0,0,20 0,1,0 2 4 255,0,0 move 5,0,0 rotate 90,0,0\n\
+      def ball sp 0,0,20 2 255,0,0\n\
+      in ball move 0,3,0 rotate 0,90,0 scale 1,1,2\n\
+    ")).unwrap();
+    let bounds: Vec<_> = scene.objects.iter().map(|o| o.bounding_box().unwrap()).collect();
+    let expected = [
+      (Point3::new(0.0, 0.0, 20.0), Vec3::new(4.0, 4.0, 4.0)),
+      (Point3::new(5.0, 0.0, 20.0), Vec3::new(2.0, 2.0, 4.0)),
+      (Point3::new(0.0, 3.0, 20.0), Vec3::new(2.0, 2.0, 4.0)),
+    ];
+    for (bounds, (center, size)) in bounds.iter().zip(expected) {
+      assert!((bounds.centroid() - center).length() < 1e-9, "{:?}", bounds.centroid());
+      assert!((bounds.max - bounds.min - size).length() < 1e-9, "{:?}", bounds.max - bounds.min);
+    }
+  }
+}
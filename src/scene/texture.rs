@@ -0,0 +1,294 @@
+// surface colors looked up per hit, from the surface (u, v) coordinates or the hit point
+//
+// procedural patterns are evaluated in world space at the hit point, so they need no
+// texture coordinates and keep scenes free of image files
+//
+// image textures are decoded into linear values once at load time, from sRGB unless
+// they hold data such as roughness, v = 0 is the bottom row of the image like in OBJ
+// texture coordinates
+
+use std::fmt;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+
+use crate::color::{ srgb_to_linear, Color };
+use crate::math::{ Point3, Vec3 };
+use crate::scene::noise::{ worley, Perlin };
+
+pub type SharedTexture = Arc<dyn Texture + Send + Sync>;
+
+// noise layers summed by the turbulence based patterns
+const TURBULENCE_OCTAVES: u32 = 7;
+
+pub trait Texture {
+  fn value(&self, uv: (f64, f64), point: Point3) -> Color;
+}
+
+#[derive(Debug)]
+pub struct TextureError {
+  pub file   : PathBuf,
+  pub message: String,
+}
+
+impl fmt::Display for TextureError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.file.display(), self.message)
+  }
+}
+
+impl std::error::Error for TextureError {}
+
+pub struct Constant {
+  pub color: Color,
+}
+
+// uniform color, what materials without a texture use
+pub fn constant(color: Color) -> SharedTexture {
+  Arc::new(Constant { color })
+}
+
+// another texture multiplied by a color, how glTF combines factors and textures
+pub struct Tint {
+  pub texture: SharedTexture,
+  pub factor : Vec3,
+}
+
+// alternating squares in uv space, `scale` squares per unit of u and v
+pub struct Checker {
+  pub even : SharedTexture,
+  pub odd  : SharedTexture,
+  pub scale: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pattern {
+  Noise,      // smooth perlin noise
+  Turbulence, // layered absolute noise, cloudy
+  Marble,     // stripes along x bent by turbulence
+  Wood,       // noisy rings around the y axis
+  Worley,     // distance to the closest of scattered points, pebbles and scales
+}
+
+impl Pattern {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "noise"      => Some(Pattern::Noise),
+      "turbulence" => Some(Pattern::Turbulence),
+      "marble"     => Some(Pattern::Marble),
+      "wood"       => Some(Pattern::Wood),
+      "worley"     => Some(Pattern::Worley),
+      _ => None,
+    }
+  }
+}
+
+// blends from `low` to `high` following a pattern in [0, 1],
+// `scale` is how many pattern features fit in a world unit
+pub struct Procedural {
+  pattern: Pattern,
+  low    : Vec3,
+  high   : Vec3,
+  scale  : f64,
+  perlin : Perlin,
+}
+
+impl Procedural {
+  pub fn new(pattern: Pattern, low: Color, high: Color, scale: f64) -> Self {
+    Procedural {
+      pattern,
+      low   : Vec3::from(low),
+      high  : Vec3::from(high),
+      scale,
+      perlin: Perlin::new(0),
+    }
+  }
+
+  fn pattern_value(&self, point: Point3) -> f64 {
+    let p = point * self.scale;
+    let value = match self.pattern {
+      Pattern::Noise      => 0.5 * (1.0 + self.perlin.noise(p)),
+      Pattern::Turbulence => self.perlin.turbulence(p, TURBULENCE_OCTAVES),
+      Pattern::Marble     => 0.5 * (1.0 + (p.x + 10.0 * self.perlin.turbulence(p, TURBULENCE_OCTAVES)).sin()),
+      Pattern::Wood => {
+        // a little noise keeps the rings from being perfect circles
+        let radius = (p.x * p.x + p.z * p.z).sqrt() + 0.3 * self.perlin.noise(p * 0.5);
+        (radius * 4.0).fract()
+      }
+      Pattern::Worley => worley(p).0,
+    };
+    value.clamp(0.0, 1.0)
+  }
+}
+
+// how texture coordinates outside [0, 1] are brought back onto the image
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum WrapMode {
+  #[default]
+  Repeat,
+  Clamp,  // edge texels stretch outwards
+  Mirror, // every other repetition is flipped
+}
+
+impl WrapMode {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "repeat" => Some(WrapMode::Repeat),
+      "clamp"  => Some(WrapMode::Clamp),
+      "mirror" => Some(WrapMode::Mirror),
+      _ => None,
+    }
+  }
+
+  // index of texel `i` on an axis of `size` texels
+  fn wrap(&self, i: i64, size: usize) -> usize {
+    let size = size as i64;
+    let wrapped = match self {
+      WrapMode::Repeat => i.rem_euclid(size),
+      WrapMode::Clamp  => i.clamp(0, size - 1),
+      WrapMode::Mirror => {
+        let period = i.rem_euclid(2 * size);
+        if period < size { period } else { 2 * size - 1 - period }
+      }
+    };
+    wrapped as usize
+  }
+}
+
+// what the 8-bit values of an image stand for
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Encoding {
+  #[default]
+  Srgb,   // colors
+  Linear, // data such as metallic and roughness, used as they are
+}
+
+// bilinearly filtered image in linear color, rows stored top to bottom
+pub struct ImageTexture {
+  width : usize,
+  height: usize,
+  pixels: Vec<Vec3>,
+  wrap  : WrapMode,
+}
+
+impl ImageTexture {
+  // decodes a PNG or JPEG file holding colors
+  pub fn load(path: &Path, wrap: WrapMode) -> Result<Self, TextureError> {
+    let error = |message: String| TextureError { file: path.to_path_buf(), message };
+    let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+    ImageTexture::decode(&bytes, wrap, Encoding::Srgb).map_err(error)
+  }
+
+  // decodes a PNG or JPEG file already in memory, the format is recognized from its
+  // first bytes
+  pub fn decode(bytes: &[u8], wrap: WrapMode, encoding: Encoding) -> Result<Self, String> {
+    let (width, height, channels, bytes) = match bytes {
+      [0x89, b'P', b'N', b'G', ..] => decode_png(bytes)?,
+      [0xff, 0xd8, ..]             => decode_jpeg(bytes)?,
+      _ => return Err("unsupported texture format, use png or jpeg".to_string()),
+    };
+    if width == 0 || height == 0 {
+      return Err("image is empty".to_string());
+    }
+
+    // 8-bit values to linear through a table, gray images fill every channel,
+    // alpha is dropped
+    let linear: Vec<f64> = (0..256)
+      .map(|b| match encoding {
+        Encoding::Srgb   => srgb_to_linear(b as f64 / 255.0),
+        Encoding::Linear => b as f64 / 255.0,
+      })
+      .collect();
+    let pixels = bytes
+      .chunks_exact(channels)
+      .map(|texel| match channels {
+        1 | 2 => Vec3::new(linear[texel[0] as usize], linear[texel[0] as usize], linear[texel[0] as usize]),
+        _     => Vec3::new(linear[texel[0] as usize], linear[texel[1] as usize], linear[texel[2] as usize]),
+      })
+      .collect();
+
+    Ok(ImageTexture { width, height, pixels, wrap })
+  }
+
+  fn texel(&self, x: i64, y: i64) -> Vec3 {
+    self.pixels[self.wrap.wrap(y, self.height) * self.width + self.wrap.wrap(x, self.width)]
+  }
+}
+
+// texture implementations
+
+impl Texture for Constant {
+  fn value(&self, _uv: (f64, f64), _point: Point3) -> Color {
+    self.color
+  }
+}
+
+impl Texture for Tint {
+  fn value(&self, uv: (f64, f64), point: Point3) -> Color {
+    Color::Rgb(Vec3::from(self.texture.value(uv, point)) * self.factor)
+  }
+}
+
+impl Texture for Checker {
+  fn value(&self, uv: (f64, f64), point: Point3) -> Color {
+    let square = (uv.0 * self.scale).floor() + (uv.1 * self.scale).floor();
+    if square.rem_euclid(2.0) < 1.0 {
+      self.even.value(uv, point)
+    } else {
+      self.odd.value(uv, point)
+    }
+  }
+}
+
+impl Texture for Procedural {
+  fn value(&self, _uv: (f64, f64), point: Point3) -> Color {
+    let t = self.pattern_value(point);
+    Color::Rgb(self.low * (1.0 - t) + self.high * t)
+  }
+}
+
+impl Texture for ImageTexture {
+  fn value(&self, uv: (f64, f64), _point: Point3) -> Color {
+    // texel centers sit at half-integer positions
+    let x = uv.0 * self.width as f64 - 0.5;
+    let y = (1.0 - uv.1) * self.height as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let top    = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+    let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+    Color::Rgb(top * (1.0 - fy) + bottom * fy)
+  }
+}
+
+// width, height, channels per texel and 8-bit samples
+type Decoded = (usize, usize, usize, Vec<u8>);
+
+fn decode_png(bytes: &[u8]) -> Result<Decoded, String> {
+  let mut decoder = png::Decoder::new(bytes);
+  // palettes and low bit depths are expanded, 16-bit samples cut down to 8
+  decoder.set_transformations(png::Transformations::normalize_to_color8());
+  let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+  let mut buffer = vec![0; reader.output_buffer_size()];
+  let frame = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+  buffer.truncate(frame.buffer_size());
+  Ok((frame.width as usize, frame.height as usize, frame.color_type.samples(), buffer))
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<Decoded, String> {
+  let mut decoder = jpeg_decoder::Decoder::new(bytes);
+  let mut bytes = decoder.decode().map_err(|e| e.to_string())?;
+  let info = decoder.info().ok_or("missing jpeg header")?;
+  let channels = match info.pixel_format {
+    jpeg_decoder::PixelFormat::L8    => 1,
+    jpeg_decoder::PixelFormat::RGB24 => 3,
+    // big endian, the high byte is the 8-bit value
+    jpeg_decoder::PixelFormat::L16 => {
+      bytes = bytes.chunks_exact(2).map(|sample| sample[0]).collect();
+      1
+    }
+    jpeg_decoder::PixelFormat::CMYK32 => return Err("CMYK jpegs are not supported".to_string()),
+  };
+  Ok((info.width as usize, info.height as usize, channels, bytes))
+}
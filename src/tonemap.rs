@@ -0,0 +1,78 @@
+// conversion of linear radiance to displayable 8-bit sRGB
+
+use crate::color::{ linear_to_srgb, Color };
+use crate::framebuffer::{ FrameBuffer, RadianceBuffer };
+use crate::math::Vec3;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ToneMapper {
+  // cuts everything above 1.0
+  #[default]
+  Clamp,
+  // luminance based x / (1 + x), keeps hues while compressing highlights
+  Reinhard,
+  // narkowicz's fit of the ACES filmic curve
+  Aces,
+}
+
+impl ToneMapper {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "clamp"    => Some(ToneMapper::Clamp),
+      "reinhard" => Some(ToneMapper::Reinhard),
+      "aces"     => Some(ToneMapper::Aces),
+      _ => None,
+    }
+  }
+
+  // maps scene-referred radiance into [0, 1]
+  pub fn apply(&self, rgb: Vec3) -> Vec3 {
+    match self {
+      ToneMapper::Clamp => rgb,
+      ToneMapper::Reinhard => {
+        let luminance = luminance(rgb);
+        if luminance <= 0.0 {
+          return Vec3::zero();
+        }
+        rgb * (1.0 / (1.0 + luminance))
+      }
+      ToneMapper::Aces => {
+        let curve = |x: f64| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+        Vec3::new(curve(rgb.x), curve(rgb.y), curve(rgb.z))
+      }
+    }
+    .clamp(0.0, 1.0)
+  }
+}
+
+// everything applied between the radiance buffer and the screen
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DisplayTransform {
+  pub tone_mapper: ToneMapper,
+  pub exposure   : f64, // in stops, radiance is scaled by 2^exposure before tone mapping
+}
+
+impl DisplayTransform {
+  pub fn apply(&self, rgb: Vec3) -> Color {
+    let mapped = self.tone_mapper.apply(rgb.clamp(0.0, f64::MAX) * 2f64.powf(self.exposure));
+    Color::Rgb(Vec3::new(
+      linear_to_srgb(mapped.x),
+      linear_to_srgb(mapped.y),
+      linear_to_srgb(mapped.z),
+    ))
+  }
+
+  pub fn encode(&self, radiance: &RadianceBuffer) -> FrameBuffer {
+    let mut buffer = FrameBuffer::new(radiance.width, radiance.height);
+    for (pixel, value) in buffer.buf.iter_mut().zip(&radiance.pixels) {
+      let rgb = Vec3::new(value[0] as f64, value[1] as f64, value[2] as f64);
+      *pixel = self.apply(rgb).to_rgb_bytes();
+    }
+    buffer
+  }
+}
+
+// rec. 709 relative luminance
+pub fn luminance(rgb: Vec3) -> f64 {
+  0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
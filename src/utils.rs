@@ -1,11 +1,80 @@
-use rand::{thread_rng, Rng};
+// pcg32 (XSH RR), small state and good enough statistics for sampling,
+// every pixel sample gets its own generator so images do not depend on
+// which thread rendered what
+#[derive(Clone)]
+pub struct Rng {
+  state    : u64,
+  increment: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Rng {
+  pub fn new(seed: u64, stream: u64) -> Self {
+    let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+    rng.next_u32();
+    rng.state = rng.state.wrapping_add(seed);
+    rng.next_u32();
+    rng
+  }
+
+  // generator for sample `sample` of pixel (`x`, `y`) in a render seeded with `seed`
+  pub fn for_sample(seed: u64, x: usize, y: usize, sample: usize) -> Self {
+    let pixel = mix(seed ^ mix(((y as u64) << 32) | x as u64));
+    Rng::new(mix(pixel ^ sample as u64), seed)
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    let old = self.state;
+    self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.increment);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    let rotation = (old >> 59) as u32;
+    xorshifted.rotate_right(rotation)
+  }
 
+  // uniform in [0, 1)
+  pub fn double(&mut self) -> f64 {
+    self.next_u32() as f64 / (1u64 << 32) as f64
+  }
+}
 
-pub fn random_double() -> f64 {
-  let mut rng = thread_rng();
-  rng.r#gen()
+// splitmix64 finalizer, spreads nearby keys over the whole seed space
+pub fn mix(mut z: u64) -> u64 {
+  z = z.wrapping_add(0x9e3779b97f4a7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+  z ^ (z >> 31)
 }
 
-pub fn random_double_in(min: f64, max: f64) -> f64 {
-  min + (max - min) * random_double()
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sequence(mut rng: Rng) -> Vec<u32> {
+    (0..64).map(|_| rng.next_u32()).collect()
+  }
+
+  #[test]
+  fn doubles_stay_in_unit_interval() {
+    let mut rng = Rng::new(42, 7);
+    for _ in 0..100_000 {
+      let x = rng.double();
+      assert!((0.0..1.0).contains(&x), "{x}");
+    }
+    // the largest output maps below one
+    assert!((u32::MAX as f64 / (1u64 << 32) as f64) < 1.0);
+  }
+
+  #[test]
+  fn fixed_seed_repeats() {
+    assert_eq!(sequence(Rng::new(42, 7)), sequence(Rng::new(42, 7)));
+    assert_ne!(sequence(Rng::new(42, 7)), sequence(Rng::new(43, 7)));
+    assert_ne!(sequence(Rng::new(42, 7)), sequence(Rng::new(42, 8)));
+
+    assert_eq!(sequence(Rng::for_sample(1, 3, 4, 5)), sequence(Rng::for_sample(1, 3, 4, 5)));
+    let others = [(2, 3, 4, 5), (1, 4, 3, 5), (1, 3, 4, 6)];
+    for (seed, x, y, sample) in others {
+      assert_ne!(sequence(Rng::for_sample(1, 3, 4, 5)), sequence(Rng::for_sample(seed, x, y, sample)));
+    }
+  }
 }
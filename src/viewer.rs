@@ -0,0 +1,128 @@
+// interactive window that keeps refining the image while nothing changes
+
+use std::path::Path;
+use std::process;
+use std::time::SystemTime;
+
+use minifb::{ Key, KeyRepeat, Window, WindowOptions };
+
+use crate::camera::Camera;
+use crate::framebuffer::Accumulator;
+use crate::scene::{ load_file, Scene };
+use crate::tonemap::{ DisplayTransform, ToneMapper };
+
+// camera movement per frame in scene units, and rotation per frame in degrees
+const MOVE_STEP: f64 = 0.05;
+const TURN_STEP: f64 = 1.5;
+// multiplier applied while shift is held
+const FAST: f64 = 10.0;
+// exposure change per key press, in stops
+const EXPOSURE_STEP: f64 = 0.5;
+
+// opens the window and renders until it is closed,
+// `path` is watched and the scene reloaded whenever the file is saved,
+// T cycles the tone mapper and -/= change the exposure without restarting accumulation
+pub fn run(mut scene: Scene, path: Option<&Path>) {
+  let (width, height) = (scene.camera.image_width, scene.camera.image_height);
+
+  // window setup
+  let mut window = Window::new(
+    "raytreizer",
+    width,
+    height,
+    WindowOptions {
+      resize: true,
+      ..WindowOptions::default()
+  })
+  .unwrap_or_else(|e| {
+      eprintln!("could not open a window: {e}");
+      process::exit(1);
+  });
+  window.set_target_fps(60);
+
+  let mut accumulator = Accumulator::new(width, height);
+  let mut display = DisplayTransform::default();
+  let mut modified = path.and_then(modified_time);
+
+  while window.is_open() && !window.is_key_down(Key::Escape) {
+    if let Some(path) = path {
+      let current = modified_time(path);
+      if current != modified {
+        modified = current;
+        match load_file(path, width, scene.camera.aspect_ratio) {
+          Ok(reloaded) => {
+            scene = reloaded;
+            accumulator.reset();
+          }
+          Err(e) => eprintln!("{}: {e}", path.display()),
+        }
+      }
+    }
+
+    if move_camera(&window, &mut scene.camera) {
+      accumulator.reset();
+    }
+
+    adjust_display(&window, &mut display);
+
+    accumulator.add(&scene.render(accumulator.samples), scene.camera.sampling_rate);
+    window.set_title(&format!(
+      "raytreizer - {} samples - {:?} {:+.1} EV",
+      accumulator.samples, display.tone_mapper, display.exposure,
+    ));
+    let buffer = display.encode(&accumulator.average());
+    window.update_with_buffer(&buffer.buf, width, height).unwrap();
+  }
+
+  eprintln!("bvh: {}", scene.bvh_stats());
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn adjust_display(window: &Window, display: &mut DisplayTransform) {
+  if window.is_key_pressed(Key::T, KeyRepeat::No) {
+    display.tone_mapper = match display.tone_mapper {
+      ToneMapper::Clamp    => ToneMapper::Reinhard,
+      ToneMapper::Reinhard => ToneMapper::Aces,
+      ToneMapper::Aces     => ToneMapper::Clamp,
+    };
+  }
+  if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+    display.exposure -= EXPOSURE_STEP;
+  }
+  if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+    display.exposure += EXPOSURE_STEP;
+  }
+}
+
+// WASD moves, Q/E go down/up, arrow keys turn, returns true when the camera changed
+fn move_camera(window: &Window, camera: &mut Camera) -> bool {
+  let fast = if window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift) { FAST } else { 1.0 };
+  let step = MOVE_STEP * fast;
+  let turn = (TURN_STEP * fast).to_radians();
+
+  let key = |k: Key| if window.is_key_down(k) { 1.0 } else { 0.0 };
+  let forward = key(Key::W) - key(Key::S);
+  let right   = key(Key::D) - key(Key::A);
+  let up      = key(Key::E) - key(Key::Q);
+  let yaw     = key(Key::Right) - key(Key::Left);
+  let pitch   = key(Key::Up) - key(Key::Down);
+
+  if forward == 0.0 && right == 0.0 && up == 0.0 && yaw == 0.0 && pitch == 0.0 {
+    return false;
+  }
+
+  let basis = camera.basis.clone();
+  camera.position += step * (forward * camera.direction + right * basis.u + up * camera.vup);
+
+  // rotate the view direction towards the right and up axes of the camera
+  let (yaw, pitch) = (yaw * turn, pitch * turn);
+  let direction = yaw.cos() * camera.direction + yaw.sin() * basis.u;
+  let direction = pitch.cos() * direction + pitch.sin() * basis.v;
+
+  let vup = camera.vup;
+  camera.orient(direction, vup);
+  true
+}